@@ -12,48 +12,86 @@
 //  0xFF80–0xFFFE  | 127B   | High RAM (HRAM)
 //  0xFFFF         | 1B     | Interrupt Enable Register (IE)
 
+use crate::interrupts::{Interrupt, INTERRUPTS};
+use crate::timer::Timer;
+use crate::ppu::{Mode, Ppu, Renderer};
+use crate::dma::Dma;
+use crate::joypad::Joypad;
+use crate::serial::Serial;
+use crate::cartridge::Cartridge;
+
 pub struct Bus {
 
     pub boot_rom: [u8; 0xFF + 1],
 
-    pub cartridge: [u8; 0x7FFF + 1],
+    // ROM and external RAM, reads as 0xFF while no cartridge is inserted
+    pub cartridge: Option<Cartridge>,
     pub vram: [u8; 0x1FFF + 1],
-    pub eram: [u8; 0x1FFF + 1],
     pub wram: [u8; 0x1FFF + 1],
     pub oam: [u8; 0x9F + 1],
     pub io: [u8; 0x7F + 1],
     pub hram: [u8; 0x7E + 1],
     pub ie: u8,
+
+    pub timer: Timer,
+    pub ppu: Ppu,
+    pub dma: Dma,
+    pub joypad: Joypad,
+    pub serial: Serial,
+
+    // Blocks CPU access to VRAM and OAM while the PPU is using them, can be turned off for debugging
+    ppu_access_locking: bool,
 }
 
 impl Bus {
+    #[cfg(test)]
     pub fn new() -> Self {
+        Self::with_renderer(Renderer::Scanline)
+    }
+
+    pub fn with_renderer(renderer: Renderer) -> Self {
         Self {
             boot_rom: [0; 0xFF + 1],
-            cartridge: [0; 0x7FFF + 1],
+            cartridge: None,
             vram: [0; 0x1FFF + 1],
-            eram: [0; 0x1FFF + 1],
             wram: [0; 0x1FFF + 1],
             oam: [0; 0x9F + 1],
             io: [0; 0x7F + 1],
             hram: [0; 0x7E + 1],
             ie: 0,
+            timer: Timer::new(),
+            ppu: Ppu::with_renderer(renderer),
+            dma: Dma::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            ppu_access_locking: true,
         }
     }
 
     pub fn read(&self, addr: u16) -> u8 {
+        // During OAM DMA the CPU only reaches HRAM and the I/O registers
+        if self.dma.is_active() && addr < 0xFF00 {
+            return 0xFF;
+        }
+        if self.ppu_locked(addr) {
+            return 0xFF;
+        }
+        self.read_direct(addr)
+    }
+
+    fn read_direct(&self, addr: u16) -> u8 {
         if addr < 0x8000 { // Cartridge
             if addr < 0x100 && self.io[0xFF50 - 0xFF00] == 0 {
                 return self.boot_rom[addr as usize];
             } else {
-                return self.cartridge[addr as usize];
+                return self.read_cartridge(addr);
             }
 
         } else if addr < 0xA000 { // VRAM
             return self.vram[(addr - 0x8000) as usize];
 
         } else if addr < 0xC000 { // External RAM
-            return self.vram[(addr - 0xA000) as usize];
+            return self.read_cartridge(addr);
 
         } else if addr < 0xE000 { // WRAM
             return self.wram[(addr - 0xC000) as usize];
@@ -67,6 +105,24 @@ impl Bus {
         } else if addr < 0xFF00 { // Unused
             return 0;
 
+        } else if addr == 0xFF00 { // Joypad
+            return self.joypad.read();
+
+        } else if addr == 0xFF01 || addr == 0xFF02 { // Serial
+            return self.serial.read(addr);
+
+        } else if (0xFF04..=0xFF07).contains(&addr) { // Timer
+            return self.timer.read(addr);
+
+        } else if addr == 0xFF0F { // Interrupt Flag, upper 3 bits unused
+            return self.io[0x0F] | 0xE0;
+
+        } else if addr == 0xFF46 { // OAM DMA
+            return self.dma.read();
+
+        } else if (0xFF40..=0xFF4B).contains(&addr) { // PPU
+            return self.ppu.read(addr);
+
         } else if addr < 0xFF80 { // I/O Registers
             return self.io[(addr - 0xFF00) as usize];
 
@@ -79,19 +135,91 @@ impl Bus {
         
     }
 
+    fn read_cartridge(&self, addr: u16) -> u8 {
+        match &self.cartridge {
+            Some(cartridge) => cartridge.read(addr),
+            None => 0xFF,
+        }
+    }
+
+    pub fn set_ppu_access_locking(&mut self, value: bool) {
+        self.ppu_access_locking = value;
+    }
+
+    fn ppu_locked(&self, addr: u16) -> bool {
+        // VRAM is out of reach while the PPU draws (mode 3), OAM also during OAM scan (mode 2)
+        if !self.ppu_access_locking {
+            return false;
+        }
+
+        let vram: bool = (0x8000..0xA000).contains(&addr);
+        let oam: bool = (0xFE00..0xFEA0).contains(&addr);
+        match self.ppu.mode() {
+            Mode::Drawing => vram || oam,
+            Mode::OamScan => oam,
+            _ => false,
+        }
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        // Sets the interrupt's bit in IF; the CPU services it once it is also enabled in IE
+        self.io[0x0F] |= interrupt.bit();
+    }
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.io[0x0F] &= !interrupt.bit();
+    }
+    pub fn pending_interrupts(&self) -> u8 {
+        // Interrupts that are both requested (IF) and enabled (IE)
+        self.ie & self.io[0x0F] & 0x1F
+    }
+
+    pub fn tick(&mut self) {
+        // Advances every component clocked alongside the CPU by one M-cycle.
+        if self.timer.tick() {
+            self.request_interrupt(Interrupt::Timer);
+        }
+
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.tick();
+        }
+
+        if self.serial.tick() {
+            self.request_interrupt(Interrupt::Serial);
+        }
+
+        if self.joypad.take_interrupt() {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+
+        if let Some((source, index)) = self.dma.tick() {
+            self.oam[index as usize] = self.read_direct(source);
+        }
+
+        let requested: u8 = self.ppu.tick(&self.vram, &self.oam);
+        for interrupt in INTERRUPTS {
+            if requested & interrupt.bit() != 0 {
+                self.request_interrupt(interrupt);
+            }
+        }
+    }
+
     pub fn write(&mut self, addr: u16, data: u8) {
-        if addr < 0x8000 { // Cartridge
-            if addr < 0x100 && self.io[0xFF50 - 0xFF00] == 0 {
-                self.boot_rom[addr as usize] = data;
-            } else {
-                self.cartridge[addr as usize] = data;
+        if (self.dma.is_active() && addr < 0xFF00) || self.ppu_locked(addr) {
+            return;
+        }
+
+        if addr < 0x8000 { // Cartridge, writes reach the cartridge even while the boot ROM is mapped
+            if let Some(cartridge) = &mut self.cartridge {
+                cartridge.write(addr, data);
             }
 
         } else if addr < 0xA000 { // VRAM
             self.vram[(addr - 0x8000) as usize] = data;
 
         } else if addr < 0xC000 { // External RAM
-            self.eram[(addr - 0xA000) as usize] = data;
+            if let Some(cartridge) = &mut self.cartridge {
+                cartridge.write(addr, data);
+            }
 
         } else if addr < 0xE000 { // WRAM
             self.wram[(addr - 0xC000) as usize] = data;
@@ -105,6 +233,21 @@ impl Bus {
         } else if addr < 0xFF00 { // Unused
             println!("Illegal!");
 
+        } else if (0xFF04..=0xFF07).contains(&addr) { // Timer
+            self.timer.write(addr, data);
+
+        } else if addr == 0xFF46 { // OAM DMA
+            self.dma.write(data);
+
+        } else if (0xFF40..=0xFF4B).contains(&addr) { // PPU
+            self.ppu.write(addr, data);
+
+        } else if addr == 0xFF00 { // Joypad
+            self.joypad.write(data);
+
+        } else if addr == 0xFF01 || addr == 0xFF02 { // Serial
+            self.serial.write(addr, data);
+
         } else if addr < 0xFF80 { // I/O Registers
             self.io[(addr - 0xFF00) as usize] = data;
 
@@ -117,4 +260,69 @@ impl Bus {
     }
 
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::Button;
+
+    fn bus_in_mode(mode: Mode) -> Bus {
+        let mut bus = Bus::new();
+        bus.vram[0] = 0x12;
+        bus.oam[0] = 0x34;
+        bus.write(0xFF40, 0x80);
+        while bus.ppu.mode() != mode {
+            bus.tick();
+        }
+        bus
+    }
+
+    #[test]
+    fn vram_is_locked_while_drawing() {
+        let mut bus = bus_in_mode(Mode::Drawing);
+        assert_eq!(bus.read(0x8000), 0xFF);
+        assert_eq!(bus.read(0xFE00), 0xFF);
+        bus.write(0x8000, 0x56);
+        bus.write(0xFE00, 0x78);
+        assert_eq!(bus.vram[0], 0x12);
+        assert_eq!(bus.oam[0], 0x34);
+    }
+
+    #[test]
+    fn only_oam_is_locked_during_oam_scan() {
+        let mut bus = bus_in_mode(Mode::OamScan);
+        assert_eq!(bus.read(0x8000), 0x12);
+        assert_eq!(bus.read(0xFE00), 0xFF);
+        bus.write(0x8000, 0x56);
+        assert_eq!(bus.vram[0], 0x56);
+    }
+
+    #[test]
+    fn hblank_and_vblank_leave_both_open() {
+        for mode in [Mode::HBlank, Mode::VBlank] {
+            let bus = bus_in_mode(mode);
+            assert_eq!(bus.read(0x8000), 0x12);
+            assert_eq!(bus.read(0xFE00), 0x34);
+        }
+    }
+
+    #[test]
+    fn locking_can_be_disabled() {
+        let mut bus = bus_in_mode(Mode::Drawing);
+        bus.set_ppu_access_locking(false);
+        assert_eq!(bus.read(0x8000), 0x12);
+        bus.write(0xFE00, 0x78);
+        assert_eq!(bus.oam[0], 0x78);
+    }
+
+    #[test]
+    fn button_press_requests_joypad_interrupt() {
+        let mut bus = Bus::new();
+        bus.write(0xFF00, 0x10);
+        bus.joypad.press(Button::Start);
+        bus.tick();
+        assert_eq!(bus.read(0xFF0F) & Interrupt::Joypad.bit(), Interrupt::Joypad.bit());
+        assert_eq!(bus.read(0xFF00), 0xD7);
+    }
+}
@@ -0,0 +1,361 @@
+// Cartridge ROM and external RAM, described by the header at 0x0100-0x014F.
+//
+//  0x0134-0x0143 | Title, the last bytes double as manufacturer code and CGB flag on newer cartridges
+//  0x0143        | CGB flag (0x80 supports CGB, 0xC0 CGB only)
+//  0x0144-0x0145 | New licensee code, used when the old one is 0x33
+//  0x0146        | SGB flag (0x03 supports SGB)
+//  0x0147        | Cartridge type (MBC and extra hardware)
+//  0x0148        | ROM size, 32 KiB << n
+//  0x0149        | RAM size
+//  0x014B        | Old licensee code
+//  0x014C        | Version
+//  0x014D        | Header checksum over 0x0134-0x014C, checked by the boot ROM
+//  0x014E-0x014F | Global checksum over the whole ROM, big endian, not checked by hardware
+
+use std::fmt;
+
+use crate::mbc1::Mbc1;
+use crate::mbc2::{self, Mbc2};
+use crate::mbc3::{Mbc3, RtcClock};
+use crate::mbc5::{Mbc5, MotorEvent};
+
+const HEADER_END: usize = 0x150;
+
+// Checked by the boot ROM, also used to spot the games inside MBC1 multicarts
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub title: String,
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CartridgeError {
+    TooSmall(usize),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    UnsupportedType(u8),
+    RomSizeMismatch { expected: usize, actual: usize },
+    HeaderChecksum { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::TooSmall(size) => write!(f, "ROM is {} bytes, too small to hold a header", size),
+            CartridgeError::UnknownRomSize(code) => write!(f, "Unknown ROM size code {:#04X}", code),
+            CartridgeError::UnknownRamSize(code) => write!(f, "Unknown RAM size code {:#04X}", code),
+            CartridgeError::UnsupportedType(code) => write!(f, "Unsupported cartridge type {:#04X}", code),
+            CartridgeError::RomSizeMismatch { expected, actual } => {
+                write!(f, "Header declares {} bytes of ROM but the file has {}", expected, actual)
+            },
+            CartridgeError::HeaderChecksum { expected, actual } => {
+                write!(f, "Header checksum is {:#04X} but the header adds up to {:#04X}", expected, actual)
+            },
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        let cgb_flag: u8 = rom[0x143];
+        let title_end: usize = if cgb_flag & 0x80 != 0 { 0x143 } else { 0x144 };
+        let title: String = rom[0x134..title_end].iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+            .collect();
+
+        let rom_size: usize = match rom[0x148] {
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(CartridgeError::UnknownRomSize(code)),
+        };
+        let ram_size: usize = match rom[0x149] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(CartridgeError::UnknownRamSize(code)),
+        };
+
+        let licensee: Licensee = if rom[0x14B] == 0x33 {
+            Licensee::New(String::from_utf8_lossy(&rom[0x144..0x146]).into_owned())
+        } else {
+            Licensee::Old(rom[0x14B])
+        };
+
+        Ok(Self {
+            title,
+            cgb_flag,
+            sgb_flag: rom[0x146],
+            cartridge_type: rom[0x147],
+            rom_size,
+            ram_size,
+            licensee,
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            global_checksum: u16::from_be_bytes([rom[0x14E], rom[0x14F]]),
+        })
+    }
+}
+
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..=0x14C].iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1))
+}
+
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter().enumerate()
+        .filter(|(addr, _)| *addr != 0x14E && *addr != 0x14F)
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+}
+
+enum Mbc {
+    RomOnly,
+    Mbc1(Mbc1),
+    Mbc2(Mbc2),
+    Mbc3(Mbc3),
+    Mbc5(Mbc5),
+}
+
+pub struct Cartridge {
+    pub header: Header,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: Mbc,
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        Self::with_rtc_clock(rom, RtcClock::WallClock)
+    }
+
+    pub fn with_rtc_clock(rom: Vec<u8>, clock: RtcClock) -> Result<Self, CartridgeError> {
+        // The clock source only matters for MBC3 carts with a real-time clock
+        let header: Header = Header::parse(&rom)?;
+
+        let actual: u8 = header_checksum(&rom);
+        if actual != header.header_checksum {
+            return Err(CartridgeError::HeaderChecksum { expected: header.header_checksum, actual });
+        }
+        if rom.len() < header.rom_size {
+            return Err(CartridgeError::RomSizeMismatch { expected: header.rom_size, actual: rom.len() });
+        }
+
+        let mbc: Mbc = match header.cartridge_type {
+            0x00 | 0x08 | 0x09 => Mbc::RomOnly,
+            0x01..=0x03 => Mbc::Mbc1(Mbc1::new(is_mbc1_multicart(&rom))),
+            0x05 | 0x06 => Mbc::Mbc2(Mbc2::new()),
+            0x0F | 0x10 => Mbc::Mbc3(Mbc3::new(true, clock)),
+            0x11..=0x13 => Mbc::Mbc3(Mbc3::new(false, clock)),
+            0x19..=0x1B => Mbc::Mbc5(Mbc5::new(false)),
+            0x1C..=0x1E => Mbc::Mbc5(Mbc5::new(true)),
+            code => return Err(CartridgeError::UnsupportedType(code)),
+        };
+
+        // MBC2 has its RAM built in, the header declares none
+        let ram_size: usize = if let Mbc::Mbc2(_) = mbc { mbc2::RAM_SIZE } else { header.ram_size };
+        let ram: Vec<u8> = vec![0; ram_size];
+        Ok(Self { header, rom, ram, mbc })
+    }
+
+    pub fn global_checksum_valid(&self) -> bool {
+        // Real hardware ignores this one, so a mismatch is only worth a warning
+        global_checksum(&self.rom) == self.header.global_checksum
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match &self.mbc {
+            Mbc::RomOnly => self.read_unbanked(addr),
+            Mbc::Mbc1(mbc) => mbc.read(&self.rom, &self.ram, addr),
+            Mbc::Mbc2(mbc) => mbc.read(&self.rom, &self.ram, addr),
+            Mbc::Mbc3(mbc) => mbc.read(&self.rom, &self.ram, addr),
+            Mbc::Mbc5(mbc) => mbc.read(&self.rom, &self.ram, addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match &mut self.mbc {
+            Mbc::RomOnly => self.write_unbanked(addr, data),
+            Mbc::Mbc1(mbc) => mbc.write(&mut self.ram, addr, data),
+            Mbc::Mbc2(mbc) => mbc.write(&mut self.ram, addr, data),
+            Mbc::Mbc3(mbc) => mbc.write(&mut self.ram, addr, data),
+            Mbc::Mbc5(mbc) => mbc.write(&mut self.ram, addr, data),
+        }
+    }
+
+    pub fn tick(&mut self) {
+        // Advances anything on the cartridge clocked alongside the CPU by one M-cycle
+        if let Mbc::Mbc3(mbc) = &mut self.mbc {
+            mbc.tick();
+        }
+    }
+
+    pub fn motor_on(&self) -> bool {
+        // Rumble motor state, always off on carts without one
+        match &self.mbc {
+            Mbc::Mbc5(mbc) => mbc.motor_on(),
+            _ => false,
+        }
+    }
+
+    pub fn take_motor_events(&mut self) -> Vec<MotorEvent> {
+        // Rumble motor changes since the last call, for the frontend to act on
+        match &mut self.mbc {
+            Mbc::Mbc5(mbc) => mbc.take_motor_events(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn read_unbanked(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.rom.get(addr as usize).copied().unwrap_or(0xFF),
+            0xA000..=0xBFFF => self.ram.get((addr - 0xA000) as usize).copied().unwrap_or(0xFF),
+            _ => panic!("Cartridge read out of range: {:#06X}", addr),
+        }
+    }
+
+    fn write_unbanked(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x7FFF => {}, // ROM
+            0xA000..=0xBFFF => {
+                if let Some(byte) = self.ram.get_mut((addr - 0xA000) as usize) {
+                    *byte = data;
+                }
+            },
+            _ => panic!("Cartridge write out of range: {:#06X}", addr),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+}
+
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    // MBC1M carts are 8 Mbit and have a second game, with its own header, starting at bank 0x10
+    rom.len() == 0x100000 && rom[0x40104..0x40134] == NINTENDO_LOGO
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn rom_with_header(cartridge_type: u8, rom_code: u8, ram_code: u8) -> Vec<u8> {
+        // A blank ROM of the declared size with a valid header
+        let mut rom: Vec<u8> = vec![0; 0x8000 << rom_code];
+        rom[0x134..0x134 + 6].copy_from_slice(b"TETRIS");
+        rom[0x147] = cartridge_type;
+        rom[0x148] = rom_code;
+        rom[0x149] = ram_code;
+        rom[0x14B] = 0x01;
+        rom[0x14D] = header_checksum(&rom);
+        let global: u16 = global_checksum(&rom);
+        rom[0x14E..0x150].copy_from_slice(&global.to_be_bytes());
+        rom
+    }
+
+    #[test]
+    fn parses_the_header() {
+        let mut rom = rom_with_header(0x00, 0x00, 0x02);
+        rom[0x146] = 0x03;
+        rom[0x14C] = 0x01;
+        rom[0x14D] = header_checksum(&rom);
+
+        let cartridge = Cartridge::new(rom).unwrap();
+        let header = &cartridge.header;
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.cgb_flag, 0x00);
+        assert_eq!(header.sgb_flag, 0x03);
+        assert_eq!(header.cartridge_type, 0x00);
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.ram_size, 0x2000);
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+        assert_eq!(header.version, 0x01);
+        assert_eq!(cartridge.ram().len(), 0x2000);
+    }
+
+    #[test]
+    fn cgb_flag_is_not_part_of_the_title_and_new_licensee() {
+        let mut rom = rom_with_header(0x00, 0x00, 0x00);
+        rom[0x134..0x144].copy_from_slice(b"POKEMON GOLDAAUE");
+        rom[0x143] = 0x80;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x14B] = 0x33;
+        rom[0x14D] = header_checksum(&rom);
+
+        let header = Cartridge::new(rom).unwrap().header;
+        assert_eq!(header.title, "POKEMON GOLDAAU");
+        assert_eq!(header.cgb_flag, 0x80);
+        assert_eq!(header.licensee, Licensee::New("01".to_string()));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert_eq!(Cartridge::new(vec![0; 0x100]).err(), Some(CartridgeError::TooSmall(0x100)));
+
+        let mut rom = rom_with_header(0x00, 0x00, 0x00);
+        rom[0x14D] ^= 0xFF;
+        assert!(matches!(Cartridge::new(rom).err(), Some(CartridgeError::HeaderChecksum { .. })));
+
+        let mut rom = rom_with_header(0x00, 0x00, 0x00);
+        rom[0x148] = 0x01;
+        rom[0x14D] = header_checksum(&rom);
+        assert_eq!(Cartridge::new(rom).err(), Some(CartridgeError::RomSizeMismatch { expected: 0x10000, actual: 0x8000 }));
+
+        let mut rom = rom_with_header(0x00, 0x00, 0x00);
+        rom[0x149] = 0x09;
+        rom[0x14D] = header_checksum(&rom);
+        assert_eq!(Cartridge::new(rom).err(), Some(CartridgeError::UnknownRamSize(0x09)));
+    }
+
+    #[test]
+    fn global_checksum_is_only_reported() {
+        let mut rom = rom_with_header(0x00, 0x00, 0x00);
+        assert!(Cartridge::new(rom.clone()).unwrap().global_checksum_valid());
+        rom[0x4000] = 0x12;
+        assert!(!Cartridge::new(rom).unwrap().global_checksum_valid());
+    }
+
+    #[test]
+    fn rom_is_read_only_and_ram_is_sized_by_the_header() {
+        let mut rom = rom_with_header(0x00, 0x00, 0x00);
+        rom[0x7FFF] = 0x42;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        cartridge.write(0x7FFF, 0x00);
+        assert_eq!(cartridge.read(0x7FFF), 0x42);
+        cartridge.write(0xA000, 0x12);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+
+        let mut cartridge = Cartridge::new(rom_with_header(0x00, 0x00, 0x01)).unwrap();
+        cartridge.write(0xA7FF, 0x34);
+        assert_eq!(cartridge.read(0xA7FF), 0x34);
+        assert_eq!(cartridge.read(0xA800), 0xFF);
+    }
+}
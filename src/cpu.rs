@@ -1,158 +1,530 @@
 
-use core::panic;
-
-use colored::Colorize;
-
-use crate::{bus::Bus, registers::{Registers,Register,Flag}, instructions::execute_instruction};
+use crate::{bus::Bus, registers::{Registers,Register,Operand8}, instructions::execute_instruction, interrupts::INTERRUPTS, ppu::Renderer};
 
 pub struct CPU {
     pub register: Registers,
-    pc: u16,
-    sp: u16,
     pub bus: Bus,
+    ime: bool,
+    ime_scheduled: bool,
+    halted: bool,
+    halt_bug: bool,
+    stopped: bool,
+    locked: bool,
+    cycles: u64,
+    cycle_accurate: bool,
+    instruction_ticks: u8,
     
 }
 impl CPU {
 
     pub fn new() -> Self {
-        Self {register: Registers::new(), pc: 0, sp: 0, bus: Bus::new()}
+        Self::with_renderer(Renderer::Scanline)
+    }
+
+    pub fn with_renderer(renderer: Renderer) -> Self {
+        Self {register: Registers::new(), bus: Bus::with_renderer(renderer), ime: false, ime_scheduled: false, halted: false, halt_bug: false, stopped: false, locked: false, cycles: 0, cycle_accurate: false, instruction_ticks: 0}
     }
 
     pub fn verify(&self) -> bool{
         return true;
     }
 
-    pub fn boot(&mut self){
-        let mut loops: i32 = 0;
+    pub fn boot(&mut self, mut on_frame: impl FnMut(&mut CPU)){
+        // Runs forever, handing over to the frontend each time the PPU finishes a frame
         loop {
+            self.step();
+            if self.bus.ppu.frame_complete() {
+                on_frame(self);
+            }
+        }
+    }
+
+    pub fn step(&mut self) -> u8 {
+        // Runs one instruction and returns the M-cycles it took. A halted, stopped or locked CPU idles for one M-cycle.
+        self.instruction_ticks = 0;
+
+        // EI only takes effect after the instruction following it
+        let enable_ime: bool = self.ime_scheduled;
+
+        if self.stopped && self.bus.read(0xFF00) & 0x0F != 0x0F {
+            // A selected joypad line going low brings the CPU out of STOP
+            self.stopped = false;
+        }
+
+        let cycles: u8 = if self.locked || self.stopped {
+            1
+        } else if self.ime && self.bus.pending_interrupts() != 0 {
+            self.halted = false;
+            self.service_interrupt()
+        } else if self.halted && self.bus.pending_interrupts() == 0 {
+            1
+        } else {
+            // Any enabled and requested interrupt wakes the CPU, whether or not IME is set
+            self.halted = false;
+
             let current_instruction = self.fetch_n8(); 
-            let pc_of_opcode = self.pc - 1;
+            execute_instruction(self, current_instruction)
+        };
+
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
 
-            if (pc_of_opcode == 0x68) { 
-                self.debug_state(pc_of_opcode, current_instruction, true);
+        // Whatever the instruction did not already tick through bus accesses is ticked now.
+        // STOP halts the system clock, so nothing else runs until a button wakes the CPU.
+        if !self.stopped {
+            for _ in self.instruction_ticks..cycles {
+                self.bus.tick();
             }
+        }
+
+        self.cycles += cycles as u64;
+        cycles
+    }
+    fn service_interrupt(&mut self) -> u8 {
+        // Jump to the vector of the highest priority pending interrupt.
+        // Cycles: 5 -- two waits, two pushes and setting PC
+        let pending: u8 = self.bus.pending_interrupts();
+        let interrupt = INTERRUPTS.into_iter().find(|interrupt| pending & interrupt.bit() != 0).unwrap();
 
-            execute_instruction(self, current_instruction);
+        self.ime = false;
+        self.bus.acknowledge_interrupt(interrupt);
 
-            println!("Opcode: {:#x}, PC: {:#x}, Loop: {}", current_instruction, self.pc, loops);
-            loops += 1;
+        // With the HALT bug pending, PC has not moved past the HALT yet, so that is where the handler returns to
+        let mut return_address: u16 = self.get_pc();
+        if self.halt_bug {
+            return_address = return_address.wrapping_sub(1);
+            self.halt_bug = false;
         }
+
+        self.tick_internal();
+        self.push_stack16(return_address);
+        self.set_pc(interrupt.vector());
+
+        5
+    }
+
+    pub fn set_cycle_accurate(&mut self, value: bool){
+        // When set, every bus access ticks the rest of the system by one M-cycle as it happens,
+        // instead of the whole instruction being ticked once it has finished.
+        self.cycle_accurate = value;
+    }
+    pub fn tick_internal(&mut self){
+        // An M-cycle spent without touching the bus, which still has to happen before the accesses that follow it
+        if self.cycle_accurate {
+            self.bus.tick();
+            self.instruction_ticks += 1;
+        }
+    }
+
+    pub fn read_bus(&mut self, addr: u16) -> u8 {
+        self.tick_internal();
+        self.bus.read(addr)
+    }
+    pub fn write_bus(&mut self, addr: u16, data: u8){
+        self.tick_internal();
+        self.bus.write(addr, data);
+    }
+    pub fn get_cycles(& self) -> u64 {
+        // Total M-cycles since power on, for stepping the rest of the system against
+        return self.cycles
     }
 
     pub fn fetch_n8(&mut self) -> u8{
-        let n8: u8 = self.bus.read(self.pc);
-        self.pc = self.pc.wrapping_add(0x1);
+        let pc: u16 = self.get_pc();
+        let n8: u8 = self.read_bus(pc);
+
+        if self.halt_bug {
+            // The byte after HALT is read twice
+            self.halt_bug = false;
+        } else {
+            self.set_pc(pc.wrapping_add(0x1));
+        }
         return n8;
     }
 
+    pub fn read_operand_8(&mut self, operand: &Operand8) -> u8 {
+        match operand {
+            Operand8::Register(register) => self.register.get_8(register),
+            Operand8::HL => self.read_bus(self.register.get_16(&Register::HL)),
+        }
+    }
+    pub fn write_operand_8(&mut self, operand: &Operand8, value: u8){
+        match operand {
+            Operand8::Register(register) => self.register.set_8(register, value),
+            Operand8::HL => self.write_bus(self.register.get_16(&Register::HL), value),
+        }
+    }
+
     pub fn push_stack16(&mut self, value: u16){
         let high: u8 = (value >> 8) as u8;
         let low: u8 = (value & 0xFF) as u8;
 
-        self.sp -= 1;
-        self.bus.write(self.sp, high);
-        self.sp -= 1;
-        self.bus.write(self.sp, low);
+        let mut sp: u16 = self.get_sp();
+
+        // SP is decremented during an internal M-cycle before the first write
+        self.tick_internal();
+        sp = sp.wrapping_sub(1);
+        self.write_bus(sp, high);
+        sp = sp.wrapping_sub(1);
+        self.write_bus(sp, low);
+
+        self.set_sp(sp);
     }
     pub fn pop_stack16(&mut self) -> u16{
-        let low = self.bus.read(self.sp) as u16;
-        self.sp += 1;
-        let high= self.bus.read(self.sp) as u16;
-        self.sp += 1;
+        let mut sp: u16 = self.get_sp();
+
+        let low = self.read_bus(sp) as u16;
+        sp = sp.wrapping_add(1);
+        let high= self.read_bus(sp) as u16;
+        sp = sp.wrapping_add(1);
+
+        self.set_sp(sp);
 
         (high << 8) | low
 
     }
     pub fn set_sp(&mut self, value: u16){
-        self.sp = value;
+        self.register.set_16(&Register::SP, value);
     }
     pub fn get_sp(& self) -> u16 {
-        return self.sp
+        return self.register.get_16(&Register::SP)
     }
     pub fn set_pc(&mut self, value: u16){
-        self.pc = value;
+        self.register.set_16(&Register::PC, value);
     }
     pub fn get_pc(& self) -> u16 {
-        return self.pc
+        return self.register.get_16(&Register::PC)
     }
     pub fn offset_pc(&mut self, offset: i8){
-        self.pc = self.pc.wrapping_add(offset as u16);
+        self.set_pc(self.get_pc().wrapping_add(offset as u16));
+    }
+    pub fn set_ime(&mut self, value: bool){
+        self.ime = value;
+        self.ime_scheduled = false;
+    }
+    pub fn schedule_ime(&mut self){
+        // EI sets IME only once the next instruction has run
+        if !self.ime {
+            self.ime_scheduled = true;
+        }
+    }
+    pub fn halt(&mut self){
+        if !self.ime && self.bus.pending_interrupts() != 0 {
+            // HALT bug: with IME clear and an interrupt already pending the CPU does not halt,
+            // and PC fails to increment after fetching the next opcode
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+    pub fn stop(&mut self){
+        // Writing to DIV resets it, as entering STOP does
+        self.bus.write(0xFF04, 0);
+        self.stopped = true;
+    }
+    #[cfg(test)]
+    pub fn is_halted(& self) -> bool {
+        return self.halted
+    }
+    #[cfg(test)]
+    pub fn is_stopped(& self) -> bool {
+        return self.stopped
+    }
+    pub fn lock(&mut self){
+        // Illegal opcodes hang the CPU; only a reset recovers it
+        self.locked = true;
     }
 
+}
     
 
-    pub fn debug_state(&self, pc: u16, opcode: u8, exit: bool) {
-    
-        println!("{}", format!(
-                    "\nVRAM BLOCK 0:\n{}",
-                    (0x8000..=0x8800)
-                        .map(|addr| format!("{:02X}", self.bus.read(addr as u16)))
-                        .collect::<Vec<String>>()
-                        .chunks(16) // 16 bytes per line
-                        .map(|line| line.join(" "))
-                        .collect::<Vec<String>>()
-                        .join("\n")
-                ).blue());
-        println!("{}", format!(
-                    "\nTILEMAP 0:\n{}",
-                    (0x9800..=0x9C00)
-                        .map(|addr| format!("{:02X}", self.bus.read(addr as u16)))
-                        .collect::<Vec<String>>()
-                        .chunks(32) // 16 bytes per line
-                        .map(|line| line.join(" "))
-                        .collect::<Vec<String>>()
-                        .join("\n")
-                ).green());
-        println!("{}", format!(
-                    "\nSTACK\n{}",
-                    (0xFF80..=0xFFFE)
-                        .map(|addr| {
-                            let value = self.bus.read(addr as u16);
-                            if addr == self.sp {
-                                format!("{}", format!("{:02X}", value).red())  // Highlight value at cpu.sp in red
-                            } else {
-                                format!("{:02X}", value)  // Regular formatting for other values
-                            }
-                        })
-                        .collect::<Vec<String>>()
-                        .chunks(64)  // Printing 16 bytes per line
-                        .map(|line| line.join(" "))
-                        .collect::<Vec<String>>()
-                        .join("\n")
-                ).yellow());  // This blue only applies to the full output string, not individual bytes
-    
-        println!("{}",
-                    format!("\nRegisters: A: 0x{:02X}, B: 0x{:02X}, C: 0x{:02X}, D: 0x{:02X}, E: 0x{:02X}, H: 0x{:02X}, L: 0x{:02X}, SP: 0x{:04X}, PC: 0x{:04X}",
-                    self.register.get_8(&Register::A),
-                    self.register.get_8(&Register::B),
-                    self.register.get_8(&Register::C),
-                    self.register.get_8(&Register::D),
-                    self.register.get_8(&Register::E),
-                    self.register.get_8(&Register::H),
-                    self.register.get_8(&Register::L),
-                    self.sp, 
-                    pc).bold()
-                );
-            println!("{}",
-                    format!("\nFlags: Z: {}, N: {}, H: {}, C: {}\n",
-                    self.register.get_flag(&Flag::Z),
-                    self.register.get_flag(&Flag::N),
-                    self.register.get_flag(&Flag::H),
-                    self.register.get_flag(&Flag::C)).bold()
-                );
-    
-        if exit {
-            panic!("{}", format!("Unknown opcode: 0x{:02X} at 0x{:02X}", opcode, pc).bold().red()) // Handle unknown opcode
-        } else {
-            println!("{}", format!("Unknown opcode: 0x{:02X} at 0x{:02X}", opcode, pc).bold().red())
+
+
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::interrupts::Interrupt;
+    use crate::joypad::Button;
+
+    pub fn post_boot_cpu(rom: Vec<u8>, renderer: Renderer) -> CPU {
+        // Starts a ROM at 0x0100 in the state the DMG boot ROM leaves behind, for running test ROMs without one
+        let mut cpu = CPU::with_renderer(renderer);
+        cpu.bus.cartridge = Some(Cartridge::new(rom).unwrap());
+        cpu.bus.write(0xFF50, 1);
+        cpu.bus.write(0xFF40, 0x91);
+        cpu.bus.write(0xFF47, 0xFC);
+        cpu.register.set_16(&Register::AF, 0x01B0);
+        cpu.register.set_16(&Register::BC, 0x0013);
+        cpu.register.set_16(&Register::DE, 0x00D8);
+        cpu.register.set_16(&Register::HL, 0x014D);
+        cpu.set_sp(0xFFFE);
+        cpu.set_pc(0x0100);
+        cpu.set_cycle_accurate(true);
+        cpu
+    }
+
+    pub fn cpu_with(program: &[u8]) -> CPU {
+        // Runs program from 0xC000 in WRAM, with HL pointing past it for (HL) operands
+        let mut cpu = CPU::new();
+        for (i, byte) in program.iter().enumerate() {
+            cpu.bus.write(0xC000 + i as u16, *byte);
         }
-        
-            
+        cpu.set_pc(0xC000);
+        cpu.set_sp(0xDFFE);
+        cpu.register.set_16(&Register::HL, 0xC100);
+        cpu
     }
 
+    fn cycle_accurate_cpu(program: &[u8]) -> CPU {
+        let mut cpu = cpu_with(program);
+        cpu.set_cycle_accurate(true);
+        cpu
+    }
 
-}
-    
+    #[test]
+    fn bus_accesses_never_exceed_instruction_cycles() {
+        let illegal = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+        for opcode in (0x00..=0xFF).filter(|op| !illegal.contains(op) && *op != 0xCB) {
+            let mut cpu = cycle_accurate_cpu(&[opcode, 0x00, 0xC1]);
+            let cycles = cpu.step();
+            assert!(cpu.instruction_ticks <= cycles, "opcode {:#04X}: {} > {}", opcode, cpu.instruction_ticks, cycles);
+        }
+        for opcode in 0x00..=0xFF {
+            let mut cpu = cycle_accurate_cpu(&[0xCB, opcode]);
+            let cycles = cpu.step();
+            assert!(cpu.instruction_ticks <= cycles, "CB opcode {:#04X}: {} > {}", opcode, cpu.instruction_ticks, cycles);
+        }
+    }
+
+    #[test]
+    fn stack_accesses_tick_every_cycle() {
+        // PUSH BC: fetch, internal, write, write
+        let mut cpu = cycle_accurate_cpu(&[0xC5]);
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.instruction_ticks, 4);
+
+        // CALL a16: fetch, read, read, internal, write, write
+        let mut cpu = cycle_accurate_cpu(&[0xCD, 0x00, 0xC1]);
+        assert_eq!(cpu.step(), 6);
+        assert_eq!(cpu.instruction_ticks, 6);
+    }
+
+    #[test]
+    fn interrupt_dispatch_pushes_pc_and_jumps_to_vector() {
+        let mut cpu = cpu_with(&[0x00]);
+        cpu.set_ime(true);
+        cpu.bus.ie = 0x1F;
+        cpu.bus.request_interrupt(Interrupt::Timer);
+
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.get_pc(), 0x50);
+        assert_eq!(cpu.pop_stack16(), 0xC000);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.bus.io[0x0F] & 0x1F, 0);
+    }
 
+    #[test]
+    fn interrupts_are_serviced_in_priority_order() {
+        let mut cpu = cpu_with(&[0x00]);
+        cpu.set_ime(true);
+        cpu.bus.ie = 0x1F;
+        cpu.bus.request_interrupt(Interrupt::Joypad);
+        cpu.bus.request_interrupt(Interrupt::Stat);
 
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x48);
+        assert_eq!(cpu.bus.io[0x0F] & 0x1F, Interrupt::Joypad.bit());
+    }
+
+    #[test]
+    fn disabled_interrupts_are_not_serviced() {
+        let mut cpu = cpu_with(&[0x00, 0x00]);
+        cpu.set_ime(true);
+        cpu.bus.ie = Interrupt::VBlank.bit();
+        cpu.bus.request_interrupt(Interrupt::Serial);
+
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.get_pc(), 0xC001);
+    }
 
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        let mut cpu = cpu_with(&[0xFB, 0x00, 0x00]); // EI ; NOP ; NOP
+        cpu.bus.ie = 0x1F;
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+
+        cpu.step(); // EI
+        assert!(!cpu.ime);
+        cpu.step(); // NOP runs before the interrupt
+        assert_eq!(cpu.get_pc(), 0xC002);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x40);
+    }
+
+    #[test]
+    fn di_right_after_ei_keeps_interrupts_disabled() {
+        let mut cpu = cpu_with(&[0xFB, 0xF3, 0x00]); // EI ; DI ; NOP
+        cpu.bus.ie = 0x1F;
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert!(!cpu.ime);
+        assert_eq!(cpu.get_pc(), 0xC003);
+    }
+
+    #[test]
+    fn reti_enables_interrupts_immediately() {
+        let mut cpu = cpu_with(&[0xD9]); // RETI
+        cpu.push_stack16(0xC100);
+        cpu.bus.ie = 0x1F;
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+
+        cpu.step();
+        assert!(cpu.ime);
+        assert_eq!(cpu.get_pc(), 0xC100);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x40);
+    }
+
+    #[test]
+    fn halt_waits_for_a_pending_interrupt_even_with_ime_clear() {
+        let mut cpu = cpu_with(&[0x76, 0x3C]); // HALT ; INC A
+        cpu.bus.ie = Interrupt::Timer.bit();
+
+        cpu.step();
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.get_pc(), 0xC001);
+
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.register.get_8(&Register::A), 1);
+        // Not serviced, so the request stays in IF
+        assert_eq!(cpu.bus.io[0x0F] & 0x1F, Interrupt::Timer.bit());
+    }
+
+    #[test]
+    fn halt_with_ime_set_services_the_interrupt() {
+        let mut cpu = cpu_with(&[0x76, 0x00]); // HALT ; NOP
+        cpu.set_ime(true);
+        cpu.bus.ie = Interrupt::VBlank.bit();
+
+        cpu.step();
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.get_pc(), 0x40);
+        assert_eq!(cpu.pop_stack16(), 0xC001);
+    }
+
+    #[test]
+    fn halt_bug_reads_the_next_byte_twice() {
+        let mut cpu = cpu_with(&[0x76, 0x3C, 0x00]); // HALT ; INC A ; NOP
+        cpu.bus.ie = Interrupt::VBlank.bit();
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+
+        cpu.step();
+        assert!(!cpu.is_halted());
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.register.get_8(&Register::A), 2);
+        assert_eq!(cpu.get_pc(), 0xC002);
+    }
+
+    #[test]
+    fn halt_bug_after_ei_returns_to_the_halt() {
+        let mut cpu = cpu_with(&[0xFB, 0x76, 0x00]); // EI ; HALT ; NOP
+        cpu.bus.boot_rom[0x40] = 0x3C; // INC A
+        cpu.bus.ie = Interrupt::VBlank.bit();
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.get_pc(), 0x40);
+
+        // The handler's first byte is only executed once
+        cpu.step();
+        assert_eq!(cpu.register.get_8(&Register::A), 1);
+        assert_eq!(cpu.get_pc(), 0x41);
+        assert_eq!(cpu.pop_stack16(), 0xC001);
+    }
+
+    #[test]
+    fn stop_resets_div_and_waits_for_a_button() {
+        let mut cpu = cpu_with(&[0x10, 0x00, 0x3C]); // STOP ; INC A
+        for _ in 0..300 {
+            cpu.bus.tick();
+        }
+        assert_ne!(cpu.bus.read(0xFF04), 0);
+
+        cpu.step();
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.bus.read(0xFF04), 0);
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.register.get_8(&Register::A), 0);
+
+        // The timer is not clocked while stopped
+        for _ in 0..1000 {
+            cpu.step();
+        }
+        assert_eq!(cpu.bus.read(0xFF04), 0);
+
+        cpu.bus.joypad.press(Button::A);
+        cpu.step();
+        assert!(!cpu.is_stopped());
+        assert_eq!(cpu.register.get_8(&Register::A), 1);
+    }
+
+    fn hram_cpu(program: &[u8]) -> CPU {
+        let mut cpu = cycle_accurate_cpu(&[]);
+        for (i, byte) in program.iter().enumerate() {
+            cpu.bus.write(0xFF80 + i as u16, *byte);
+        }
+        cpu.set_pc(0xFF80);
+        cpu
+    }
+
+    #[test]
+    fn dma_routine_in_hram_copies_to_oam() {
+        // LD A, 0xC1 ; LDH (0x46), A ; LD A, 40 ; wait: DEC A ; JR NZ, wait
+        let mut cpu = hram_cpu(&[0x3E, 0xC1, 0xE0, 0x46, 0x3E, 0x28, 0x3D, 0x20, 0xFD]);
+        for i in 0..0xA0 {
+            cpu.bus.write(0xC100 + i, i as u8 ^ 0x5A);
+        }
+
+        while cpu.get_pc() != 0xFF89 {
+            cpu.step();
+        }
+        assert!(!cpu.bus.dma.is_active());
+        for i in 0..0xA0 {
+            assert_eq!(cpu.bus.read(0xFE00 + i), i as u8 ^ 0x5A);
+        }
+    }
+
+    #[test]
+    fn dma_blocks_cpu_access_outside_hram() {
+        // LDH (0x46), A ; LD A, (0xC000) ; LD (0xC001), A ; LDH (0x90), A
+        let mut cpu = hram_cpu(&[0xE0, 0x46, 0xFA, 0x00, 0xC0, 0xEA, 0x01, 0xC0, 0xE0, 0x90]);
+        cpu.bus.write(0xC000, 0x12);
+        cpu.register.set_8(&Register::A, 0xC0);
+
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.register.get_8(&Register::A), 0xFF);
+        assert_eq!(cpu.bus.read(0xFF90), 0xFF);
+
+        for _ in 0..160 {
+            cpu.bus.tick();
+        }
+        assert_eq!(cpu.bus.read(0xC000), 0x12);
+        assert_eq!(cpu.bus.read(0xC001), 0x00);
+    }
+}
@@ -0,0 +1,104 @@
+// OAM DMA, clocked once per M-cycle from the bus.
+//
+//  0xFF46 | DMA | Writing XX copies 0xXX00-0xXX9F into OAM, one byte per M-cycle
+//
+// The transfer starts one M-cycle after the write and takes 160 M-cycles. While it runs the CPU
+// can only use HRAM and the I/O registers, which is why games run their DMA routine from HRAM.
+
+pub const OAM_SIZE: u8 = 0xA0;
+
+pub struct Dma {
+    register: u8,
+    source: u16,
+    // Next byte to copy, None while no transfer is running
+    index: Option<u8>,
+    // 0xFF46 was written during the last M-cycle, the transfer (re)starts on this one
+    starting: bool,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Self { register: 0, source: 0, index: None, starting: false }
+    }
+
+    pub fn tick(&mut self) -> Option<(u16, u8)> {
+        // Advances the transfer by one M-cycle. Returns the source address and OAM offset of the byte to copy.
+        let transfer: Option<(u16, u8)> = self.index.map(|index| (self.source + index as u16, index));
+
+        if let Some(index) = self.index {
+            self.index = if index + 1 < OAM_SIZE { Some(index + 1) } else { None };
+        }
+
+        if self.starting {
+            self.starting = false;
+            // Sources above 0xDF00 read the echo of WRAM
+            self.source = if self.register >= 0xE0 { self.register as u16 - 0x20 } else { self.register as u16 } << 8;
+            self.index = Some(0);
+        }
+
+        transfer
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.register = data;
+        self.starting = true;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.index.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_starts_one_cycle_after_the_write() {
+        let mut dma = Dma::new();
+        dma.write(0xC1);
+        assert_eq!(dma.read(), 0xC1);
+        assert!(!dma.is_active());
+
+        assert_eq!(dma.tick(), None);
+        assert!(dma.is_active());
+        assert_eq!(dma.tick(), Some((0xC100, 0)));
+        assert_eq!(dma.tick(), Some((0xC101, 1)));
+    }
+
+    #[test]
+    fn transfer_copies_160_bytes() {
+        let mut dma = Dma::new();
+        dma.write(0x80);
+        dma.tick();
+
+        let copied: Vec<(u16, u8)> = (0..200).filter_map(|_| dma.tick()).collect();
+        assert_eq!(copied.len(), 160);
+        assert_eq!(copied[159], (0x809F, 0x9F));
+        assert!(!dma.is_active());
+    }
+
+    #[test]
+    fn writing_during_a_transfer_restarts_it() {
+        let mut dma = Dma::new();
+        dma.write(0xC0);
+        for _ in 0..50 {
+            dma.tick();
+        }
+        dma.write(0xD0);
+        assert_eq!(dma.tick(), Some((0xC031, 0x31)));
+        assert_eq!(dma.tick(), Some((0xD000, 0)));
+    }
+
+    #[test]
+    fn high_sources_read_the_wram_echo() {
+        let mut dma = Dma::new();
+        dma.write(0xFE);
+        dma.tick();
+        assert_eq!(dma.tick(), Some((0xDE00, 0)));
+    }
+}
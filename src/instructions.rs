@@ -1,107 +1,156 @@
-use core::panic;
-use colored::Colorize;
-
 use crate::cpu::CPU;
-use crate::registers::{Register,Flag};
-
-pub fn execute_instruction(cpu: &mut CPU, opcode: u8) {
+use crate::registers::{Register,Operand8,Flag};
 
-    let pc_of_ins = cpu.get_pc() - 1;
+pub fn execute_instruction(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Returns the number of M-cycles (4 clock ticks each) the instruction took
 
     match opcode {
 
-        0x31 => ld_sp_n16(cpu, opcode), // LD SP n16
-        0xAF => xor_a_r8(cpu, opcode), // XOR A, A
-        0x21 => ld_r16_n16(cpu, opcode), // LD HL, n16
+        0x00 => 1, // NOP
+        0x10 => stop(cpu, opcode), // STOP n8
+        0x76 => halt(cpu, opcode), // HALT
+        0xF3 => di(cpu, opcode), // DI
+        0xFB => ei(cpu, opcode), // EI
+
+        // 8-bit loads
+        0x40..=0x75 | 0x77..=0x7F => ld_r8_r8(cpu, opcode), // LD r8, r8
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => ld_r8_n8(cpu, opcode), // LD r8, n8
+        0x02 | 0x12 => ld_r16_a(cpu, opcode), // LD (r16), A
+        0x0A | 0x1A => ld_a_r16(cpu, opcode), // LD A, (r16)
+        0x22 => ld_hli_a(cpu, opcode), // LD (HL+), A
         0x32 => ld_hld_a(cpu, opcode), // LD (HL-), A
-        0x20 => jr_cc_n16(cpu, opcode), // JR NZ, e8
-        0x0E => ld_r8_n8(cpu, opcode), // LD C, n8
-        0x3E => ld_r8_n8(cpu, opcode), // LD A, n8
-        0xE2 => ldh_c_a(cpu, opcode), // LDH (C), A
-        0x0C => inc_r8(cpu, opcode), // INC C
-        0x77 => ld_hl_r8(cpu, opcode), // LD (HL), A
+        0x2A => ld_a_hdi(cpu, opcode), // LD A, (HL+)
+        0x3A => ld_a_hld(cpu, opcode), // LD A, (HL-)
+        0xEA => ld_n16_a(cpu, opcode), // LD a16, A
+        0xFA => ld_a_n16(cpu, opcode), // LD A, a16
         0xE0 => ldh_n16_a(cpu, opcode), // LDH (a8), A
-        0x11 => ld_r16_n16(cpu, opcode), // LD DE, n16
-        0x1A => ld_a_r16(cpu, opcode), // LD A, (r16)
-        0xCD => call(cpu, opcode), // CALL n16
-        0x4F => ld_r8_r8(cpu, opcode), // LD C, A
-        0x06 => ld_r8_n8(cpu, opcode), // LD B, n8
-        0xC5 => push_r16(cpu, opcode), // PUSH BC
+        0xF0 => ldh_a_n16(cpu, opcode), // LDH A, (a8)
+        0xE2 => ldh_c_a(cpu, opcode), // LDH (C), A
+        0xF2 => ldh_a_c(cpu, opcode), // LDH A, (C)
+
+        // 16-bit loads
+        0x01 | 0x11 | 0x21 | 0x31 => ld_r16_n16(cpu, opcode), // LD r16, n16
+        0x08 => ld_n16_sp(cpu, opcode), // LD (a16), SP
+        0xF8 => ld_hl_sp_add_e8(cpu, opcode), // LD HL, SP + e8
+        0xF9 => ld_sp_hl(cpu, opcode), // LD SP, HL
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => push_r16(cpu, opcode), // PUSH r16
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => pop_r16(cpu, opcode), // POP r16
+
+        // 8-bit arithmetic and logic
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => inc_r8(cpu, opcode), // INC r8
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => dec_r8(cpu, opcode), // DEC r8
+        0x80..=0x87 => add_a_r8(cpu, opcode), // ADD A, r8
+        0x88..=0x8F => adc_a_r8(cpu, opcode), // ADC A, r8
+        0x90..=0x97 => sub_r8(cpu, opcode), // SUB A, r8
+        0x98..=0x9F => sbc_a_r8(cpu, opcode), // SBC A, r8
+        0xA0..=0xA7 => and_a_r8(cpu, opcode), // AND A, r8
+        0xA8..=0xAF => xor_a_r8(cpu, opcode), // XOR A, r8
+        0xB0..=0xB7 => or_a_r8(cpu, opcode), // OR A, r8
+        0xB8..=0xBF => cp_a_r8(cpu, opcode), // CP A, r8
+        0xC6 => add_a_n8(cpu, opcode), // ADD A, n8
+        0xCE => adc_a_n8(cpu, opcode), // ADC A, n8
+        0xD6 => sub_a_n8(cpu, opcode), // SUB A, n8
+        0xDE => sbc_a_n8(cpu, opcode), // SBC A, n8
+        0xE6 => and_a_n8(cpu, opcode), // AND A, n8
+        0xEE => xor_a_n8(cpu, opcode), // XOR A, n8
+        0xF6 => or_a_n8(cpu, opcode), // OR A, n8
+        0xFE => cp_a_n8(cpu, opcode), // CP A, n8
+        0x27 => daa(cpu, opcode), // DAA
+        0x2F => cpl(cpu, opcode), // CPL
+        0x37 => scf(cpu, opcode), // SCF
+        0x3F => ccf(cpu, opcode), // CCF
+
+        // 16-bit arithmetic
+        0x03 | 0x13 | 0x23 | 0x33 => inc_r16(cpu, opcode), // INC r16
+        0x0B | 0x1B | 0x2B | 0x3B => dec_r16(cpu, opcode), // DEC r16
+        0x09 | 0x19 | 0x29 | 0x39 => add_hl_r16(cpu, opcode), // ADD HL, r16
+        0xE8 => add_sp_e8(cpu, opcode), // ADD SP, e8
+
+        // Rotates on A
+        0x07 => rlca(cpu, opcode), // RLCA
+        0x0F => rrca(cpu, opcode), // RRCA
         0x17 => rla(cpu, opcode), // RLA
-        0xC1 => pop_r16(cpu, opcode), // POP BC
-        0x05 => dec_r8(cpu, opcode), // DEC B
-        0x22 => ld_hli_a(cpu, opcode), // LD (HL+) A
-        0x23 => inc_r16(cpu, opcode), // INC HL
+        0x1F => rra(cpu, opcode), // RRA
+
+        // Jumps and subroutines
+        0x18 => jr_n16(cpu, opcode), // JR e8
+        0x20 | 0x28 | 0x30 | 0x38 => jr_cc_n16(cpu, opcode), // JR cc, e8
+        0xC3 => jp_n16(cpu, opcode), // JP a16
+        0xC2 | 0xCA | 0xD2 | 0xDA => jp_cc_n16(cpu, opcode), // JP cc, a16
+        0xE9 => jp_hl(cpu, opcode), // JP HL
+        0xCD => call(cpu, opcode), // CALL a16
+        0xC4 | 0xCC | 0xD4 | 0xDC => call_cc_n16(cpu, opcode), // CALL cc, a16
         0xC9 => ret(cpu, opcode), // RET
-        0x13 => inc_r16(cpu, opcode), // INC DE
-        0x7B => ld_r8_r8(cpu, opcode), // LD A E
-        0xFE => cp_a_n8(cpu, opcode), // CP A n8
-        0xEA => ld_n16_a(cpu, opcode), // LD a16 A
-        0x3D => dec_r8(cpu, opcode), // DEC A
-        0x28 => jr_cc_n16(cpu, opcode), // JR Z, e8
-        0x67 => ld_r8_r8(cpu, opcode), // LD H A
-        0x57 => ld_r8_r8(cpu, opcode), // LD D A
-        0x04 => inc_r8(cpu, opcode), // INC B
-        0x1E => ld_r8_n8(cpu, opcode), // LD E n8
-        0xF0 => ldh_a_n16(cpu, opcode), // LDH A a8
-        0x0D => dec_r8(cpu, opcode), // DEC C
-        0x2E => ld_r8_n8(cpu, opcode), // LD L n8
-        0x18 => jr_n16(cpu, opcode), // JR n16
-
-
-        
-    
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => ret_cc(cpu, opcode), // RET cc
+        0xD9 => reti(cpu, opcode), // RETI
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => rst_vec(cpu, opcode), // RST vec
+
+        // Illegal opcodes hang the CPU until it is reset
+        0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+            cpu.lock();
+            1
+        },
 
         0xCB => {
 
             let next = cpu.fetch_n8();
 
-            match next {
-                0x7C => bit_u3_r8(cpu, next), // BIT 7 H
-                0x11 => rl_r8(cpu, next), // RL C
-                _ => panic!("Unknown CB opcode: 0x{:02X} at 0x{:02X}", opcode, pc_of_ins), // Handle unknown opcode
+            // xx yyy zzz: x selects the group, y the operation or bit, z the r8 operand
+            match next >> 6 {
+                0b00 => match (next >> 3) & 0b0000_0111 {
+                    0b000 => rlc_r8(cpu, next), // RLC r8
+                    0b001 => rrc_r8(cpu, next), // RRC r8
+                    0b010 => rl_r8(cpu, next), // RL r8
+                    0b011 => rr_r8(cpu, next), // RR r8
+                    0b100 => sla_r8(cpu, next), // SLA r8
+                    0b101 => sra_r8(cpu, next), // SRA r8
+                    0b110 => swap_r8(cpu, next), // SWAP r8
+                    _ => srl_r8(cpu, next), // SRL r8
+                },
+                0b01 => bit_u3_r8(cpu, next), // BIT u3, r8
+                0b10 => res_u3_r8(cpu, next), // RES u3, r8
+                _ => set_u3_r8(cpu, next), // SET u3, r8
             }
 
 
         }
-        _ => {
-
-            cpu.debug_state(pc_of_ins, opcode, true);
-        }
     }
 }
     
 
 // Load instructions
-fn ld_r8_r8(cpu: &mut CPU, opcode: u8){
+fn ld_r8_r8(cpu: &mut CPU, opcode: u8) -> u8 {
     // Copy (aka Load) the value in register on the right into the register on the left.
-    // Cycles: 1 -- Bytes: 1 -- Flags: None
+    // Cycles: 1 (2 for (HL)) -- Bytes: 1 -- Flags: None
     let src: u8 = opcode & 0b0000_0111;
     let des: u8 = (opcode >> 3) & 0b0000_0111;
 
-    let src_register: Register = cpu.register.decode_register_8(src);
-    let des_register: Register = cpu.register.decode_register_8(des);
+    let src_operand: Operand8 = cpu.register.decode_register_8(src);
+    let des_operand: Operand8 = cpu.register.decode_register_8(des);
 
-    let value = cpu.register.get_8(&src_register);
-    cpu.register.set_8(&des_register, value);
+    let value = cpu.read_operand_8(&src_operand);
+    cpu.write_operand_8(&des_operand, value);
+
+    if src_operand.is_memory() || des_operand.is_memory() { 2 } else { 1 }
 }
-fn ld_r8_n8(cpu: &mut CPU, opcode: u8) {
+fn ld_r8_n8(cpu: &mut CPU, opcode: u8) -> u8 {
     // Copy the value n8 into register r8.
-    // Cycles: 2 -- Bytes: 2 -- Flags: None
+    // Cycles: 2 (3 for (HL)) -- Bytes: 2 -- Flags: None
 
     let n8 = cpu.fetch_n8();
     let des: u8 = (opcode >> 3) & 0b0000_0111;
 
-    let des_register: Register = cpu.register.decode_register_8(des);
+    let des_operand: Operand8 = cpu.register.decode_register_8(des);
 
-    cpu.register.set_8(&des_register,n8);
+    cpu.write_operand_8(&des_operand, n8);
 
+    if des_operand.is_memory() { 3 } else { 2 }
 }
-fn ld_r16_n16(cpu: &mut CPU, opcode: u8){
+fn ld_r16_n16(cpu: &mut CPU, opcode: u8) -> u8 {
     // Copy the value n16 into register r16.
     // Cycles: 3 -- Bytes: 3 -- Flags: None
 
-    let des: u8 = (opcode >> 3) & 0b0000_0111;
+    let des: u8 = (opcode >> 4) & 0b0000_0011;
 
     let register = cpu.register.decode_register_16(des);
 
@@ -111,49 +160,23 @@ fn ld_r16_n16(cpu: &mut CPU, opcode: u8){
     let n16: u16 = (high_byte as u16) << 8 | low_byte as u16;
 
     cpu.register.set_16(&register, n16);
-}
-fn ld_hl_r8(cpu: &mut CPU, opcode: u8){
-    // Copy the value in register r8 into the byte pointed to by HL.
-    // Cycles: 3 -- Bytes: 2 -- Flags: None
-
-    let src: u8 = (opcode ) & 0b0000_0111;
 
-    let src_register: Register = cpu.register.decode_register_8(src);
-
-    cpu.bus.write(cpu.register.get_16(&Register::HL), cpu.register.get_8(&src_register));
+    3
 }
-fn ld_hl_n8(cpu: &mut CPU, opcode: u8){
-    // Copy the value n8 into the byte pointed to by HL.
-    // Cycles: 3 -- Bytes: 2 -- Flags: None
-
-    let n8: u8 = cpu.fetch_n8();
-
-    cpu.bus.write(cpu.register.get_16(&Register::HL), n8);
-}
-fn ld_r8_hl(cpu: &mut CPU, opcode: u8){
-    // Copy the value pointed to by HL into register r8.
-    // Cycles: 2 -- Bytes: 1 -- Flags: None
-
-    let des: u8 = (opcode >> 3) & 0b0000_0111;
-
-    let des_register: Register = cpu.register.decode_register_8(des);
-
-    let value: u8 = cpu.bus.read(cpu.register.get_16(&Register::HL));
-
-    cpu.register.set_8(&des_register, value);
-}
-fn ld_r16_a(cpu: &mut CPU, opcode: u8){
+fn ld_r16_a(cpu: &mut CPU, opcode: u8) -> u8 {
     // Copy the value in register A into the byte pointed to by r16.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
-    let des: u8 = (opcode >> 3) & 0b0000_0111;
+    let des: u8 = (opcode >> 4) & 0b0000_0011;
 
     let des_register= cpu.register.decode_register_16(des);
 
     let addr = cpu.register.get_16(&des_register);
     let data = cpu.register.get_8(&Register::A);
-    cpu.bus.write(addr, data);
+    cpu.write_bus(addr, data);
+
+    2
 }
-fn ld_n16_a(cpu: &mut CPU, opcode: u8){
+fn ld_n16_a(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the value in register A into the byte at address n16.
     // Cycles: 4 -- Bytes: 3 -- Flags: None
     
@@ -163,9 +186,11 @@ fn ld_n16_a(cpu: &mut CPU, opcode: u8){
     let addr: u16 = (high_byte as u16) << 8 | low_byte as u16;
     let data: u8 = cpu.register.get_8(&Register::A);
 
-    cpu.bus.write(addr, data);
+    cpu.write_bus(addr, data);
+
+    4
 }
-fn ldh_n16_a(cpu: &mut CPU, opcode: u8){
+fn ldh_n16_a(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the value in register A into the byte at address n16, provided the address is between $FF00 and $FFFF.
     // Cycles: 3 -- Bytes: 2 -- Flags: None
 
@@ -174,234 +199,516 @@ fn ldh_n16_a(cpu: &mut CPU, opcode: u8){
     let addr = 0xFF00 + byte as u16;
     let data = cpu.register.get_8(&Register::A);
     
-    cpu.bus.write(addr, data);
+    cpu.write_bus(addr, data);
+
+    3
 }
-fn ldh_c_a(cpu: &mut CPU, opcode: u8){
+fn ldh_c_a(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the value in register A into the byte at address $FF00+C.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
 
     let addr: u16 = 0xFF00 + (cpu.register.get_8(&Register::C) as u16);
     let data = cpu.register.get_8(&Register::A);
 
-    cpu.bus.write(addr, data);
+    cpu.write_bus(addr, data);
+
+    2
 }
-fn ld_a_r16(cpu: &mut CPU, opcode: u8){
+fn ld_a_r16(cpu: &mut CPU, opcode: u8) -> u8 {
     // Copy the byte pointed to by r16 into register A.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
 
-    let src: u8 = (opcode >> 3) & 0b0000_0111;
+    let src: u8 = (opcode >> 4) & 0b0000_0011;
 
     let src_register= cpu.register.decode_register_16(src);
 
-    let value: u8 = cpu.bus.read(cpu.register.get_16(&src_register));
+    let value: u8 = cpu.read_bus(cpu.register.get_16(&src_register));
     cpu.register.set_8(&Register::A, value);
+
+    2
 }
-fn ld_a_n16(cpu: &mut CPU, opcode: u8){
+fn ld_a_n16(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the byte at address n16 into register A.
     // Cycles: 4 -- Bytes: 3 -- Flags: None
 
-    let high_byte: u8 = cpu.fetch_n8();
     let low_byte: u8 = cpu.fetch_n8();
+    let high_byte: u8 = cpu.fetch_n8();
 
     let addr: u16 = (high_byte as u16) << 8 | low_byte as u16;
-    let value: u8 = cpu.bus.read(addr);
+    let value: u8 = cpu.read_bus(addr);
 
     cpu.register.set_8(&Register::A, value);
+
+    4
 }
-fn ldh_a_n16(cpu: &mut CPU, opcode: u8){
+fn ldh_a_n16(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the byte at address n16 into register A.
     // Cycles: 3 -- Bytes: 2 -- Flags: None
 
     let byte: u8 = cpu.fetch_n8();
 
-    let value: u8 = cpu.bus.read(0xFF00 + (byte as u16));
+    let value: u8 = cpu.read_bus(0xFF00 + (byte as u16));
 
     cpu.register.set_8(&Register::A, value);
+
+    3
 }
-fn ldh_a_c(cpu: &mut CPU, opcode: u8){
+fn ldh_a_c(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the byte at address $FF00+C into register A.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
 
-    let value: u8 = cpu.bus.read(0xFF00 + (cpu.register.get_8(&Register::C) as u16));
+    let value: u8 = cpu.read_bus(0xFF00 + (cpu.register.get_8(&Register::C) as u16));
     cpu.register.set_8(&Register::A, value);
+
+    2
 }
-fn ld_hli_a(cpu: &mut CPU, opcode: u8){
+fn ld_hli_a(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the value in register A into the byte pointed by HL and increment HL afterwards.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
 
     let hl: u16 = cpu.register.get_16(&Register::HL);
     let data: u8 = cpu.register.get_8(&Register::A);
-    cpu.bus.write(hl, data);
-    cpu.register.set_16(&Register::HL, hl + 1);
+    cpu.write_bus(hl, data);
+    cpu.register.set_16(&Register::HL, hl.wrapping_add(1));
+
+    2
 }
-fn ld_hld_a(cpu: &mut CPU, opcode: u8){
+fn ld_hld_a(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the value in register A into the byte pointed by HL and decrement HL afterwards.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
 
     let hl: u16 = cpu.register.get_16(&Register::HL);
     let data: u8 = cpu.register.get_8(&Register::A);
-    cpu.bus.write(hl, data);
-    cpu.register.set_16(&Register::HL, hl - 1);
+    cpu.write_bus(hl, data);
+    cpu.register.set_16(&Register::HL, hl.wrapping_sub(1));
+
+    2
 }
-fn ld_a_hld(cpu: &mut CPU, opcode: u8){
+fn ld_a_hld(cpu: &mut CPU, _opcode: u8) -> u8 {
     //Copy the byte pointed to by HL into register A, and decrement HL afterwards.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
 
     let hl: u16 = cpu.register.get_16(&Register::HL);
-    let value: u8 = cpu.bus.read(hl);
+    let value: u8 = cpu.read_bus(hl);
 
     cpu.register.set_8(&Register::A, value);
-    cpu.register.set_16(&Register::HL, hl - 1);
+    cpu.register.set_16(&Register::HL, hl.wrapping_sub(1));
+
+    2
 }
-fn ld_a_hdi(cpu: &mut CPU, opcode: u8){
+fn ld_a_hdi(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy the byte pointed to by HL into register A, and increment HL afterwards.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
     let hl: u16 = cpu.register.get_16(&Register::HL);
-    let value: u8 = cpu.bus.read(hl);
+    let value: u8 = cpu.read_bus(hl);
 
     cpu.register.set_8(&Register::A, value);
-    cpu.register.set_16(&Register::HL, hl + 1); 
-}
-fn ld_sp_n16(cpu: &mut CPU, opcode: u8){  
-    // Copy the value n16 into register SP.
-    // Cycles: 3 -- Bytes: 3 -- Flags: None
-    let low_bytes: u8 = cpu.fetch_n8();
-    let high_bytes: u8 = cpu.fetch_n8();
+    cpu.register.set_16(&Register::HL, hl.wrapping_add(1));
 
-    let n16: u16 = (high_bytes as u16) << 8 | low_bytes as u16;
-
-    cpu.set_sp(n16);
+    2
 }
-fn ld_n16_sp(cpu: &mut CPU, opcode: u8){
+fn ld_n16_sp(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy SP & $FF at address n16 and SP >> 8 at address n16 + 1.
-    // Cycles: 5 -- Bytes: 2 -- Flags: None
-    todo!();
+    // Cycles: 5 -- Bytes: 3 -- Flags: None
+    let low_byte: u8 = cpu.fetch_n8();
+    let high_byte: u8 = cpu.fetch_n8();
+
+    let addr: u16 = (high_byte as u16) << 8 | low_byte as u16;
+    let sp: u16 = cpu.get_sp();
+
+    cpu.write_bus(addr, (sp & 0xFF) as u8);
+    cpu.write_bus(addr.wrapping_add(1), (sp >> 8) as u8);
+
+    5
 }
-fn ld_hl_sp_add_e8(cpu: &mut CPU, opcode: u8){
+fn ld_hl_sp_add_e8(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Add the signed value e8 to SP and copy the result in HL.
-    // Cycles: 3 -- Bytes: 2 -- Flags: H set if overflow from bit 3, C set if overflow from bit 7
-    todo!();
+    // Cycles: 3 -- Bytes: 2 -- Flags: Z 0, N 0, H set if overflow from bit 3, C set if overflow from bit 7
+    let e8: i8 = cpu.fetch_n8() as i8;
+
+    let result: u16 = sp_add_e8(cpu, e8);
+    cpu.register.set_16(&Register::HL, result);
+
+    3
 }
-fn ld_sp_hl(cpu: &mut CPU, opcode: u8){
+fn ld_sp_hl(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Copy register HL into register SP.
     // Cycles: 2 -- Bytes: 1 -- Flags: None
 
     cpu.set_sp(cpu.register.get_16(&Register::HL));
 
+    2
+}
+fn sp_add_e8(cpu: &mut CPU, e8: i8) -> u16 {
+    // Shared by ADD SP, e8 and LD HL, SP + e8: the carries come from the low byte as an unsigned add.
+    let sp: u16 = cpu.get_sp();
+    let offset: u16 = e8 as u8 as u16;
+
+    cpu.register.set_flag(&Flag::Z, false);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, (sp & 0x0F) + (offset & 0x0F) > 0x0F);
+    cpu.register.set_flag(&Flag::C, (sp & 0xFF) + (offset & 0xFF) > 0xFF);
+
+    sp.wrapping_add(e8 as u16)
 }
 
 // arthimetc
-fn inc_r8(cpu: &mut CPU, opcode: u8){
+fn inc_r8(cpu: &mut CPU, opcode: u8) -> u8 {
     // Increment the value in register r8.
-    // Cycles: 1 -- Bytes: 1 -- Flags: Z set if result is 0, N 0, H set if overflow from bit 3
+    // Cycles: 1 (3 for (HL)) -- Bytes: 1 -- Flags: Z set if result is 0, N 0, H set if overflow from bit 3
     let r8 = (opcode >> 3) & 0b0000_0111;
 
-    let r8_register: Register = cpu.register.decode_register_8(r8);
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
 
-    let value: u8 = cpu.register.get_8(&r8_register).wrapping_add(1);
-    cpu.register.set_8(&r8_register, value);
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = value.wrapping_add(1);
+    cpu.write_operand_8(&r8_operand, result);
 
+    cpu.register.set_flag(&Flag::Z, result == 0);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, (value & 0x0F) == 0x0F);
+
+    if r8_operand.is_memory() { 3 } else { 1 }
 }
-fn inc_r16(cpu: &mut CPU, opcode: u8){
+fn inc_r16(cpu: &mut CPU, opcode: u8) -> u8 {
     // Increment the value in register r16 by 1.
     // Cycles: 2 -- Bytes: 1: Flags: None
-    let r16 = (opcode >> 3) & 0b0000_0111;
+    let r16 = (opcode >> 4) & 0b0000_0011;
 
     let r16_register: Register = cpu.register.decode_register_16(r16);
 
     let value = cpu.register.get_16(&r16_register).wrapping_add(1);
     cpu.register.set_16(&r16_register, value);
+
+    2
 }
-fn dec_r8(cpu: &mut CPU, opcode: u8){
+fn dec_r8(cpu: &mut CPU, opcode: u8) -> u8 {
     // Decrement the value in register r8.
-    // Cycles: 1, Bytes: 1, Flags: Z: set if result is 0, N 1, H set if borrow from bit 4
+    // Cycles: 1 (3 for (HL)), Bytes: 1, Flags: Z: set if result is 0, N 1, H set if borrow from bit 4
     let r8 = (opcode >> 3) & 0b0000_0111;
 
-    let r8_register: Register = cpu.register.decode_register_8(r8);
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
 
-    let value: u8 = cpu.register.get_8(&r8_register).wrapping_sub(1);
-    cpu.register.set_8(&r8_register, value);
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = value.wrapping_sub(1);
+    cpu.write_operand_8(&r8_operand, result);
     
-    cpu.register.set_flag(&Flag::Z, value == 0);
-
+    cpu.register.set_flag(&Flag::Z, result == 0);
+    cpu.register.set_flag(&Flag::N, true);
+    cpu.register.set_flag(&Flag::H, (value & 0x0F) == 0);
 
+    if r8_operand.is_memory() { 3 } else { 1 }
 }
-fn sub_r8(cpu: &mut CPU, opcode: u8){
+fn sub_r8(cpu: &mut CPU, opcode: u8) -> u8 {
     // Subtract the value in register r8 from the value in register A and store the result in register A.
-    // Cycles: 1, Bytes: 1, Flags: Z: set if result is 0, N 1, H set if borrow from bit 4, C set if borrow (ie r8 > A)
+    // Cycles: 1 (2 for (HL)), Bytes: 1, Flags: Z: set if result is 0, N 1, H set if borrow from bit 4, C set if borrow (ie r8 > A)
     let r8: u8 = opcode & 0b0000_0111;
 
-    let r8_register: Register = cpu.register.decode_register_8(r8);
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
 
-    let result: u8 = cpu.register.get_8(&Register::A).wrapping_sub(cpu.register.get_8(&r8_register));
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = alu_sub(cpu, value, false);
     cpu.register.set_8(&Register::A, result);
 
+    if r8_operand.is_memory() { 2 } else { 1 }
 }
-fn cp_a_r8(cpu: &mut CPU, opcode: u8){
+fn cp_a_r8(cpu: &mut CPU, opcode: u8) -> u8 {
     // ComPare the value in A with the value in r8.
-    // Cycles: 1 -- Bytes: 1 -- Flags: Z if result is 0, N 1, H if borrow from bit 4, C if r8 > a
+    // Cycles: 1 (2 for (HL)) -- Bytes: 1 -- Flags: Z if result is 0, N 1, H if borrow from bit 4, C if r8 > a
     let src = opcode & 0b0000_0111;
-    let src_register = cpu.register.decode_register_8(src);
+    let src_operand = cpu.register.decode_register_8(src);
 
-    let a = cpu.register.get_8(&Register::A);
-    let r8 = cpu.register.get_8(&src_register);
+    let r8 = cpu.read_operand_8(&src_operand);
+    alu_sub(cpu, r8, false);
 
-    let result = a.wrapping_sub(r8);
+    if src_operand.is_memory() { 2 } else { 1 }
+}
+fn cp_a_n8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // ComPare the value in A with the value n8.
+    // Cycles: 2 -- Bytes: 2 -- Flags: Z if result is 0, N 1, H if borrow from bit 4, C if n8 > a
+    let n8 = cpu.fetch_n8();
+    alu_sub(cpu, n8, false);
+
+    2
+}
+
+fn dec_r16(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Decrement the value in register r16 by 1.
+    // Cycles: 2 -- Bytes: 1 -- Flags: None
+    let r16 = (opcode >> 4) & 0b0000_0011;
+
+    let r16_register: Register = cpu.register.decode_register_16(r16);
+
+    let value = cpu.register.get_16(&r16_register).wrapping_sub(1);
+    cpu.register.set_16(&r16_register, value);
+
+    2
+}
+fn add_hl_r16(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Add the value in r16 to HL.
+    // Cycles: 2 -- Bytes: 1 -- Flags: N 0, H set if overflow from bit 11, C set if overflow from bit 15
+    let r16 = (opcode >> 4) & 0b0000_0011;
+
+    let r16_register: Register = cpu.register.decode_register_16(r16);
+
+    let value: u16 = cpu.register.get_16(&r16_register);
+    add_hl(cpu, value);
+
+    2
+}
+fn add_hl(cpu: &mut CPU, value: u16){
+    let hl: u16 = cpu.register.get_16(&Register::HL);
+    let (result, carry) = hl.overflowing_add(value);
+
+    cpu.register.set_16(&Register::HL, result);
+
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF);
+    cpu.register.set_flag(&Flag::C, carry);
+}
+fn add_sp_e8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Add the signed value e8 to SP.
+    // Cycles: 4 -- Bytes: 2 -- Flags: Z 0, N 0, H set if overflow from bit 3, C set if overflow from bit 7
+    let e8: i8 = cpu.fetch_n8() as i8;
+
+    let result: u16 = sp_add_e8(cpu, e8);
+    cpu.set_sp(result);
+
+    4
+}
+fn add_a_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Add the value in r8 to A.
+    // Cycles: 1 (2 for (HL)) -- Bytes: 1 -- Flags: Z set if result is 0, N 0, H set if overflow from bit 3, C set if overflow from bit 7
+    let r8: u8 = opcode & 0b0000_0111;
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    alu_add(cpu, value, false);
+
+    if r8_operand.is_memory() { 2 } else { 1 }
+}
+fn add_a_n8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Add the value n8 to A.
+    // Cycles: 2 -- Bytes: 2 -- Flags: Z set if result is 0, N 0, H set if overflow from bit 3, C set if overflow from bit 7
+    let value: u8 = cpu.fetch_n8();
+    alu_add(cpu, value, false);
+
+    2
+}
+fn adc_a_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Add the value in r8 plus the carry flag to A.
+    // Cycles: 1 (2 for (HL)) -- Bytes: 1 -- Flags: Z set if result is 0, N 0, H set if overflow from bit 3, C set if overflow from bit 7
+    let r8: u8 = opcode & 0b0000_0111;
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let carry: bool = cpu.register.get_flag(&Flag::C);
+    alu_add(cpu, value, carry);
+
+    if r8_operand.is_memory() { 2 } else { 1 }
+}
+fn adc_a_n8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Add the value n8 plus the carry flag to A.
+    // Cycles: 2 -- Bytes: 2 -- Flags: Z set if result is 0, N 0, H set if overflow from bit 3, C set if overflow from bit 7
+    let value: u8 = cpu.fetch_n8();
+    let carry: bool = cpu.register.get_flag(&Flag::C);
+    alu_add(cpu, value, carry);
+
+    2
+}
+fn sub_a_n8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Subtract the value n8 from A.
+    // Cycles: 2 -- Bytes: 2 -- Flags: Z set if result is 0, N 1, H set if borrow from bit 4, C set if borrow
+    let value: u8 = cpu.fetch_n8();
+    let result: u8 = alu_sub(cpu, value, false);
+    cpu.register.set_8(&Register::A, result);
+
+    2
+}
+fn sbc_a_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Subtract the value in r8 and the carry flag from A.
+    // Cycles: 1 (2 for (HL)) -- Bytes: 1 -- Flags: Z set if result is 0, N 1, H set if borrow from bit 4, C set if borrow
+    let r8: u8 = opcode & 0b0000_0111;
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let carry: bool = cpu.register.get_flag(&Flag::C);
+    let result: u8 = alu_sub(cpu, value, carry);
+    cpu.register.set_8(&Register::A, result);
+
+    if r8_operand.is_memory() { 2 } else { 1 }
+}
+fn sbc_a_n8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Subtract the value n8 and the carry flag from A.
+    // Cycles: 2 -- Bytes: 2 -- Flags: Z set if result is 0, N 1, H set if borrow from bit 4, C set if borrow
+    let value: u8 = cpu.fetch_n8();
+    let carry: bool = cpu.register.get_flag(&Flag::C);
+    let result: u8 = alu_sub(cpu, value, carry);
+    cpu.register.set_8(&Register::A, result);
+
+    2
+}
+fn daa(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Decimal Adjust Accumulator after a BCD addition or subtraction.
+    // Cycles: 1 -- Bytes: 1 -- Flags: Z set if result is 0, H 0, C set or left untouched depending on the adjustment
+    let mut a: u8 = cpu.register.get_8(&Register::A);
+    let mut carry: bool = cpu.register.get_flag(&Flag::C);
+
+    if !cpu.register.get_flag(&Flag::N) {
+        if carry || a > 0x99 {
+            a = a.wrapping_add(0x60);
+            carry = true;
+        }
+        if cpu.register.get_flag(&Flag::H) || (a & 0x0F) > 0x09 {
+            a = a.wrapping_add(0x06);
+        }
+    } else {
+        if carry {
+            a = a.wrapping_sub(0x60);
+        }
+        if cpu.register.get_flag(&Flag::H) {
+            a = a.wrapping_sub(0x06);
+        }
+    }
+
+    cpu.register.set_8(&Register::A, a);
+
+    cpu.register.set_flag(&Flag::Z, a == 0);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, carry);
+
+    1
+}
+fn cpl(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // ComPLement accumulator (A = ~A).
+    // Cycles: 1 -- Bytes: 1 -- Flags: N 1, H 1
+    let a: u8 = cpu.register.get_8(&Register::A);
+    cpu.register.set_8(&Register::A, !a);
 
-    cpu.register.set_flag(&Flag::Z, result == 0);
     cpu.register.set_flag(&Flag::N, true);
-    cpu.register.set_flag(&Flag::H, (a & 0x0F) < (r8 & 0x0F));
-    cpu.register.set_flag(&Flag::C, a < r8);
+    cpu.register.set_flag(&Flag::H, true);
 
+    1
 }
-fn cp_a_n8(cpu: &mut CPU, opcode: u8){
+fn scf(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Set Carry Flag.
+    // Cycles: 1 -- Bytes: 1 -- Flags: N 0, H 0, C 1
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, true);
 
-    let a = cpu.register.get_8(&Register::A);
-    let n8 = cpu.fetch_n8();
+    1
+}
+fn ccf(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Complement Carry Flag.
+    // Cycles: 1 -- Bytes: 1 -- Flags: N 0, H 0, C inverted
+    let carry: bool = cpu.register.get_flag(&Flag::C);
+
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, !carry);
 
-    let result = a.wrapping_sub(n8);
+    1
+}
+fn alu_add(cpu: &mut CPU, value: u8, carry: bool){
+    // Shared by ADD and ADC: A = A + value + carry.
+    let a: u8 = cpu.register.get_8(&Register::A);
+    let carry: u8 = carry as u8;
+
+    let result: u16 = a as u16 + value as u16 + carry as u16;
+    cpu.register.set_8(&Register::A, result as u8);
+
+    cpu.register.set_flag(&Flag::Z, result as u8 == 0);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, (a & 0x0F) + (value & 0x0F) + carry > 0x0F);
+    cpu.register.set_flag(&Flag::C, result > 0xFF);
+}
+fn alu_sub(cpu: &mut CPU, value: u8, carry: bool) -> u8 {
+    // Shared by SUB, SBC and CP: returns A - value - carry and leaves storing it to the caller.
+    let a: u8 = cpu.register.get_8(&Register::A);
+    let carry: u8 = carry as u8;
+
+    let result: u8 = a.wrapping_sub(value).wrapping_sub(carry);
 
     cpu.register.set_flag(&Flag::Z, result == 0);
     cpu.register.set_flag(&Flag::N, true);
-    cpu.register.set_flag(&Flag::H, (a & 0x0F) < (n8 & 0x0F));
-    cpu.register.set_flag(&Flag::C, a < n8);
+    cpu.register.set_flag(&Flag::H, (a & 0x0F) < (value & 0x0F) + carry);
+    cpu.register.set_flag(&Flag::C, (a as u16) < value as u16 + carry as u16);
 
+    result
 }
 
 // jump and subroutine 
-fn jr_cc_n16(cpu: &mut CPU, opcode: u8){
+fn condition_met(cpu: &CPU, opcode: u8) -> bool {
+    // The condition cc sits in bits 4-3 of JR/JP/CALL/RET cc: NZ, Z, NC, C.
+    let condition: u8 = (opcode >> 3) & 0b0000_0011;
+
+    match condition {
+        0b00 => !cpu.register.get_flag(&Flag::Z),
+        0b01 => cpu.register.get_flag(&Flag::Z),
+        0b10 => !cpu.register.get_flag(&Flag::C),
+        _ => cpu.register.get_flag(&Flag::C),
+    }
+}
+fn jr_cc_n16(cpu: &mut CPU, opcode: u8) -> u8 {
     // Jump to the address PC + n16 if the condition specified by CC is met.
     // Cycles: 3 met else 2 -- Bytes: 2 -- Flags None
 
     let jump_offset: i8 = cpu.fetch_n8() as i8;
 
-    let expected_output: bool = ((opcode >> 3) & 0b0000_0001) == 1; 
-    let condition: u8 = (opcode >> 4) & 0b0000_0011; // condition to check
-
-    let condition_index = match condition {
-        0b10 => Flag::Z, // Zero flag
-        0b11 => Flag::C, // Carry flag
-        _=> panic!("Invalid condition"),
-    };
-
-    if cpu.register.get_flag(&condition_index) == expected_output { // if z = 0
-            cpu.offset_pc(jump_offset);
-        }
+    if condition_met(cpu, opcode) {
+        cpu.offset_pc(jump_offset);
+        return 3;
     }
+    2
+}
 
-fn jr_n16(cpu: &mut CPU, opcode: u8){
+fn jr_n16(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Relative Jump to address n6.
     // Cycles: 3 -- Bytes: 2 -- Flags: None
 
     let jump_offset: i8 = cpu.fetch_n8() as i8;
 
-    cpu.offset_pc(jump_offset)
+    cpu.offset_pc(jump_offset);
+
+    3
+}
+fn jp_n16(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Jump to address n16.
+    // Cycles: 4 -- Bytes: 3 -- Flags: None
+    let low_bytes: u8 = cpu.fetch_n8();
+    let high_bytes: u8 = cpu.fetch_n8();
+
+    let n16: u16 = (high_bytes as u16) << 8 | low_bytes as u16;
+
+    cpu.set_pc(n16);
+
+    4
+}
+fn jp_cc_n16(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Jump to address n16 if the condition specified by cc is met.
+    // Cycles: 4 met else 3 -- Bytes: 3 -- Flags: None
+    let low_bytes: u8 = cpu.fetch_n8();
+    let high_bytes: u8 = cpu.fetch_n8();
+
+    let n16: u16 = (high_bytes as u16) << 8 | low_bytes as u16;
 
+    if condition_met(cpu, opcode) {
+        cpu.set_pc(n16);
+        return 4;
+    }
+    3
+}
+fn jp_hl(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Jump to address in HL.
+    // Cycles: 1 -- Bytes: 1 -- Flags: None
+    cpu.set_pc(cpu.register.get_16(&Register::HL));
 
+    1
 }
-fn call(cpu: &mut CPU, opcode: u8){
+fn call(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Call address n16.
-    // Cycles: 3 met else 2 -- Bytes: 3 -- Flags None
+    // Cycles: 6 -- Bytes: 3 -- Flags None
     let low_bytes: u8 = cpu.fetch_n8();
     let high_bytes: u8 = cpu.fetch_n8();
 
@@ -412,81 +719,326 @@ fn call(cpu: &mut CPU, opcode: u8){
 
     cpu.set_pc(n16);
 
+    6
 }
-fn ret(cpu: &mut CPU, opcode: u8) {
+fn call_cc_n16(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Call address n16 if the condition specified by cc is met.
+    // Cycles: 6 met else 3 -- Bytes: 3 -- Flags None
+    let low_bytes: u8 = cpu.fetch_n8();
+    let high_bytes: u8 = cpu.fetch_n8();
+
+    let n16: u16 = (high_bytes as u16) << 8 | low_bytes as u16;
+
+    if condition_met(cpu, opcode) {
+        cpu.push_stack16(cpu.get_pc());
+        cpu.set_pc(n16);
+        return 6;
+    }
+    3
+}
+fn ret(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Return from subroutine.
     // Cycles: 4 -- Bytes: 1 -- Flags: None
     let value =  cpu.pop_stack16();
     cpu.set_pc(value);
 
+    4
 }
-fn ret_cc(cpu: &mut CPU, opcode: u8) {
+fn ret_cc(cpu: &mut CPU, opcode: u8) -> u8 {
     // Return from subroutine if condition cc is met.
     // Cycles 5 if met else 2 -- Bytes: 1 -- Flags: None
 
+    cpu.tick_internal();
+    if condition_met(cpu, opcode) {
+        let value = cpu.pop_stack16();
+        cpu.set_pc(value);
+        return 5;
+    }
+    2
+}
+fn reti(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Return from subroutine and enable interrupts.
+    // Cycles: 4 -- Bytes: 1 -- Flags: None
     let value = cpu.pop_stack16();
+    cpu.set_pc(value);
+    cpu.set_ime(true);
 
-    let expected_output: bool = ((opcode >> 3) & 0b0000_0001) == 1; // expected output of the condition
-    let condition: u8 = (opcode >> 4) & 0b0000_0011; // condition to check
-
-    let condition_index = match condition {
-        0b10 => Flag::Z, // Zero flag
-        0b11 => Flag::C, // Carry flag
-        _=> panic!("{:#b}", condition),
-    };
+    4
+}
+fn rst_vec(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Call address vec, encoded in bits 5-3 of the opcode ($00, $08, ... $38).
+    // Cycles: 4 -- Bytes: 1 -- Flags: None
+    let vec: u16 = (opcode & 0b0011_1000) as u16;
 
+    cpu.push_stack16(cpu.get_pc());
+    cpu.set_pc(vec);
 
-    if cpu.register.get_flag(&condition_index) == expected_output {
-        cpu.set_pc(value);
-    }
+    4
 }
 
 // Stack manipulatiuon
-fn push_r16(cpu: &mut CPU, opcode: u8) {
+fn push_r16(cpu: &mut CPU, opcode: u8) -> u8 {
     // Push register r16 into the stack
     //Cycles: 4 -- Bytes: 1 -- Flags: None
 
-    let r16_index = (opcode >> 3) & 0b0000_0111;
+    let r16_index = (opcode >> 4) & 0b0000_0011;
 
-    let register= cpu.register.decode_register_16(r16_index);
+    let register= cpu.register.decode_register_16_stack(r16_index);
 
     let value: u16 = cpu.register.get_16(&register);
 
     cpu.push_stack16(value);
 
+    4
 }
 
 // bitwise logic
-fn xor_a_r8(cpu: &mut CPU, opcode: u8){
+fn xor_a_r8(cpu: &mut CPU, opcode: u8) -> u8 {
     // XOR the value in register r8 with the value in register A and store the result in register A
-    // Cycles: 1, Bytes: 1, Flags: Z: set if result is 0, N 0, H 0, C 0
+    // Cycles: 1 (2 for (HL)), Bytes: 1, Flags: Z: set if result is 0, N 0, H 0, C 0
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    alu_xor(cpu, value);
+
+    if r8_operand.is_memory() { 2 } else { 1 }
+}
+fn xor_a_n8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // XOR the value n8 with the value in register A and store the result in register A
+    // Cycles: 2, Bytes: 2, Flags: Z: set if result is 0, N 0, H 0, C 0
+    let value: u8 = cpu.fetch_n8();
+    alu_xor(cpu, value);
+
+    2
+}
+fn and_a_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // AND the value in register r8 with the value in register A and store the result in register A
+    // Cycles: 1 (2 for (HL)), Bytes: 1, Flags: Z: set if result is 0, N 0, H 1, C 0
     let r8: u8 = opcode & 0b0000_0111;
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    alu_and(cpu, value);
 
-    let r8_register: Register = cpu.register.decode_register_8(r8);
+    if r8_operand.is_memory() { 2 } else { 1 }
+}
+fn and_a_n8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // AND the value n8 with the value in register A and store the result in register A
+    // Cycles: 2, Bytes: 2, Flags: Z: set if result is 0, N 0, H 1, C 0
+    let value: u8 = cpu.fetch_n8();
+    alu_and(cpu, value);
+
+    2
+}
+fn or_a_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // OR the value in register r8 with the value in register A and store the result in register A
+    // Cycles: 1 (2 for (HL)), Bytes: 1, Flags: Z: set if result is 0, N 0, H 0, C 0
+    let r8: u8 = opcode & 0b0000_0111;
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    alu_or(cpu, value);
+
+    if r8_operand.is_memory() { 2 } else { 1 }
+}
+fn or_a_n8(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // OR the value n8 with the value in register A and store the result in register A
+    // Cycles: 2, Bytes: 2, Flags: Z: set if result is 0, N 0, H 0, C 0
+    let value: u8 = cpu.fetch_n8();
+    alu_or(cpu, value);
+
+    2
+}
+fn alu_and(cpu: &mut CPU, value: u8){
+    let result: u8 = cpu.register.get_8(&Register::A) & value;
+    cpu.register.set_8(&Register::A, result);
 
-    let result: u8 = cpu.register.get_8(&Register::A) ^ cpu.register.get_8(&r8_register);
+    cpu.register.set_flag(&Flag::Z, result == 0);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, true);
+    cpu.register.set_flag(&Flag::C, false);
+}
+fn alu_xor(cpu: &mut CPU, value: u8){
+    let result: u8 = cpu.register.get_8(&Register::A) ^ value;
+    cpu.register.set_8(&Register::A, result);
 
+    cpu.register.set_flag(&Flag::Z, result == 0);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, false);
+}
+fn alu_or(cpu: &mut CPU, value: u8){
+    let result: u8 = cpu.register.get_8(&Register::A) | value;
     cpu.register.set_8(&Register::A, result);
+
+    cpu.register.set_flag(&Flag::Z, result == 0);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, false);
 }
 
 // but shift
-fn bit_u3_r8(cpu: &mut CPU, opcode: u8){
+fn bit_u3_r8(cpu: &mut CPU, opcode: u8) -> u8 {
     // Test the value in register r8 against the bit specified by u3.
-    // Cycles: 2 -- Bytes: 2, Flags: Z if selected bit is 0, N 0, H, 1
+    // Cycles: 2 (3 for (HL)) -- Bytes: 2, Flags: Z if selected bit is 0, N 0, H, 1
     let u3: u8 = (opcode >> 3) & 0b0000_0111;
     let r8: u8 = opcode & 0b0000_0111;
 
-    let r8_register: Register = cpu.register.decode_register_8(r8);
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
 
-    let value: u8 = cpu.register.get_8(&r8_register);
+    let value: u8 = cpu.read_operand_8(&r8_operand);
 
     let result: u8 = value & (1 << u3);
     
     cpu.register.set_flag(&Flag::Z, result == 0);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, true);
+
+    if r8_operand.is_memory() { 3 } else { 2 }
+}
+fn res_u3_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Set bit u3 in register r8 to 0. Bit 0 is the rightmost one, bit 7 the leftmost one.
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: None
+    let u3: u8 = (opcode >> 3) & 0b0000_0111;
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    cpu.write_operand_8(&r8_operand, value & !(1 << u3));
+
+    if r8_operand.is_memory() { 4 } else { 2 }
+}
+fn set_u3_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Set bit u3 in register r8 to 1. Bit 0 is the rightmost one, bit 7 the leftmost one.
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: None
+    let u3: u8 = (opcode >> 3) & 0b0000_0111;
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    cpu.write_operand_8(&r8_operand, value | (1 << u3));
+
+    if r8_operand.is_memory() { 4 } else { 2 }
+}
+fn rlc_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Rotate register r8 left.
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C set according to result
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = value.rotate_left(1);
+
+    cpu.write_operand_8(&r8_operand, result);
+    shift_flags(cpu, result, value & (1 << 7) != 0);
+
+    if r8_operand.is_memory() { 4 } else { 2 }
+}
+fn rrc_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Rotate register r8 right.
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C set according to result
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = value.rotate_right(1);
+
+    cpu.write_operand_8(&r8_operand, result);
+    shift_flags(cpu, result, value & 1 != 0);
+
+    if r8_operand.is_memory() { 4 } else { 2 }
+}
+fn rr_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Rotate register r8 right, through the carry flag.
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C set according to result
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let carry_flag: bool = cpu.register.get_flag(&Flag::C);
+    let result: u8 = (value >> 1) | (carry_flag as u8) << 7;
+
+    cpu.write_operand_8(&r8_operand, result);
+    shift_flags(cpu, result, value & 1 != 0);
+
+    if r8_operand.is_memory() { 4 } else { 2 }
+}
+fn sla_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Shift Left Arithmetically register r8.
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C set according to result
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = value << 1;
+
+    cpu.write_operand_8(&r8_operand, result);
+    shift_flags(cpu, result, value & (1 << 7) != 0);
+
+    if r8_operand.is_memory() { 4 } else { 2 }
+}
+fn sra_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Shift Right Arithmetically register r8 (bit 7 of r8 is unchanged).
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C set according to result
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = (value >> 1) | (value & (1 << 7));
+
+    cpu.write_operand_8(&r8_operand, result);
+    shift_flags(cpu, result, value & 1 != 0);
+
+    if r8_operand.is_memory() { 4 } else { 2 }
+}
+fn srl_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Shift Right Logically register r8.
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C set according to result
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = value >> 1;
+
+    cpu.write_operand_8(&r8_operand, result);
+    shift_flags(cpu, result, value & 1 != 0);
+
+    if r8_operand.is_memory() { 4 } else { 2 }
+}
+fn swap_r8(cpu: &mut CPU, opcode: u8) -> u8 {
+    // Swap the upper 4 bits in register r8 and the lower 4 ones.
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C 0
+    let r8: u8 = opcode & 0b0000_0111;
+
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8);
+
+    let value: u8 = cpu.read_operand_8(&r8_operand);
+    let result: u8 = value.rotate_left(4);
+
+    cpu.write_operand_8(&r8_operand, result);
+    shift_flags(cpu, result, false);
+
+    if r8_operand.is_memory() { 4 } else { 2 }
 }
-fn rla(cpu: &mut CPU, opcode: u8) {
+fn shift_flags(cpu: &mut CPU, result: u8, carry: bool){
+    cpu.register.set_flag(&Flag::Z, result == 0);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, carry);
+}
+fn rla(cpu: &mut CPU, _opcode: u8) -> u8 {
     // Rotate register A left, through the carry flag.
-    // Cycles: 2 -- Bytes: 1 -- Flags: Z 0, N 0, H 0, C set according to result
+    // Cycles: 1 -- Bytes: 1 -- Flags: Z 0, N 0, H 0, C set according to result
 
     let mut value: u8 = cpu.register.get_8(&Register::A);
 
@@ -502,44 +1054,695 @@ fn rla(cpu: &mut CPU, opcode: u8) {
     cpu.register.set_flag(&Flag::N, false);
     cpu.register.set_flag(&Flag::H, false);
 
+    1
+}
+fn rlca(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Rotate register A left.
+    // Cycles: 1 -- Bytes: 1 -- Flags: Z 0, N 0, H 0, C set according to result
+    let value: u8 = cpu.register.get_8(&Register::A);
+
+    cpu.register.set_8(&Register::A, value.rotate_left(1));
+
+    cpu.register.set_flag(&Flag::Z, false);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, value & (1 << 7) != 0);
+
+    1
+}
+fn rrca(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Rotate register A right.
+    // Cycles: 1 -- Bytes: 1 -- Flags: Z 0, N 0, H 0, C set according to result
+    let value: u8 = cpu.register.get_8(&Register::A);
+
+    cpu.register.set_8(&Register::A, value.rotate_right(1));
+
+    cpu.register.set_flag(&Flag::Z, false);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, value & 1 != 0);
+
+    1
+}
+fn rra(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Rotate register A right, through the carry flag.
+    // Cycles: 1 -- Bytes: 1 -- Flags: Z 0, N 0, H 0, C set according to result
+    let value: u8 = cpu.register.get_8(&Register::A);
+    let carry_flag: bool = cpu.register.get_flag(&Flag::C);
+
+    cpu.register.set_8(&Register::A, (value >> 1) | (carry_flag as u8) << 7);
 
+    cpu.register.set_flag(&Flag::Z, false);
+    cpu.register.set_flag(&Flag::N, false);
+    cpu.register.set_flag(&Flag::H, false);
+    cpu.register.set_flag(&Flag::C, value & 1 != 0);
 
+    1
 }
-fn rl_r8(cpu: &mut CPU, opcode: u8){
+fn rl_r8(cpu: &mut CPU, opcode: u8) -> u8 {
     // Rotate bits in register r8 left, through the carry flag.
-    // Cycles: 2 -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C Set according to result
+    // Cycles: 2 (4 for (HL)) -- Bytes: 2, Flags: Z set if result is 0, N 0, H 0, C Set according to result
 
     let r8_index = (opcode) & 0b0000_0111;
-    let r8_register: Register = cpu.register.decode_register_8(r8_index);
 
-    let mut value: u8 = cpu.register.get_8(&r8_register);
+    let r8_operand: Operand8 = cpu.register.decode_register_8(r8_index);
+
+    let mut value: u8 = cpu.read_operand_8(&r8_operand);
 
     let carry_flag: bool = cpu.register.get_flag(&Flag::C);
     let leaving_bit: u8 = value & (1 << 7);
 
     value = value << 1;
-    cpu.register.set_flag(&Flag::C, leaving_bit != 0);
-
     value |= carry_flag as u8;
 
-    cpu.register.set_8(&r8_register, value);
-    cpu.register.set_flag(&Flag::Z, leaving_bit == 0);
-    cpu.register.set_flag(&Flag::N, false);
-    cpu.register.set_flag(&Flag::H, false);
+    cpu.write_operand_8(&r8_operand, value);
+    shift_flags(cpu, value, leaving_bit != 0);
+
+    if r8_operand.is_memory() { 4 } else { 2 }
 }
 
 // stack
-fn pop_r16(cpu: &mut CPU, opcode: u8) {
+fn pop_r16(cpu: &mut CPU, opcode: u8) -> u8 {
     // Pop register r16 from the stack. This is roughly equivalent to the following imaginary instructions:
     // Cycles: 3 -- Bytes: 1 -- Flags: None
 
-    let r16_index = (opcode >> 3) & 0b0000_0111;
+    let r16_index = (opcode >> 4) & 0b0000_0011;
 
-    let register= cpu.register.decode_register_16(r16_index);
+    let register= cpu.register.decode_register_16_stack(r16_index);
 
     let value = cpu.pop_stack16();
 
     cpu.register.set_16(&register, value);
 
+    3
+}
+
+// cpu control
+fn stop(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Enter very low-power mode until a joypad button is pressed. STOP is followed by a padding byte which is skipped.
+    // Cycles: 1 -- Bytes: 2 -- Flags: None
+    cpu.set_pc(cpu.get_pc().wrapping_add(1));
+    cpu.stop();
+
+    1
+}
+fn halt(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Stop fetching instructions until an interrupt is pending.
+    // Cycles: 1 -- Bytes: 1 -- Flags: None
+    cpu.halt();
+
+    1
+}
+fn di(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Disable interrupts by clearing the IME flag.
+    // Cycles: 1 -- Bytes: 1 -- Flags: None
+    cpu.set_ime(false);
+
+    1
+}
+fn ei(cpu: &mut CPU, _opcode: u8) -> u8 {
+    // Enable interrupts by setting the IME flag. The flag is only set after the instruction following EI.
+    // Cycles: 1 -- Bytes: 1 -- Flags: None
+    cpu.schedule_ime();
+
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::cpu_with;
+
+    fn step(cpu: &mut CPU) -> u8 {
+        let opcode = cpu.fetch_n8();
+        execute_instruction(cpu, opcode)
+    }
+
+    fn flags(cpu: &CPU) -> (bool, bool, bool, bool) {
+        (
+            cpu.register.get_flag(&Flag::Z),
+            cpu.register.get_flag(&Flag::N),
+            cpu.register.get_flag(&Flag::H),
+            cpu.register.get_flag(&Flag::C),
+        )
+    }
+
+    fn set_flags(cpu: &mut CPU, z: bool, n: bool, h: bool, c: bool) {
+        cpu.register.set_flag(&Flag::Z, z);
+        cpu.register.set_flag(&Flag::N, n);
+        cpu.register.set_flag(&Flag::H, h);
+        cpu.register.set_flag(&Flag::C, c);
+    }
+
+    // 8-bit increment/decrement
+
+    #[test]
+    fn inc_r8_half_carry_and_keeps_carry() {
+        let mut cpu = cpu_with(&[0x04]); // INC B
+        cpu.register.set_8(&Register::B, 0x0F);
+        set_flags(&mut cpu, true, true, false, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::B), 0x10);
+        assert_eq!(flags(&cpu), (false, false, true, true));
+    }
+
+    #[test]
+    fn inc_r8_wraps_to_zero() {
+        let mut cpu = cpu_with(&[0x3C]); // INC A
+        cpu.register.set_8(&Register::A, 0xFF);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, false, true, false));
+    }
+
+    #[test]
+    fn dec_r8_half_borrow_and_keeps_carry() {
+        let mut cpu = cpu_with(&[0x0D]); // DEC C
+        cpu.register.set_8(&Register::C, 0x10);
+        set_flags(&mut cpu, true, false, false, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::C), 0x0F);
+        assert_eq!(flags(&cpu), (false, true, true, true));
+    }
+
+    #[test]
+    fn dec_r8_to_zero() {
+        let mut cpu = cpu_with(&[0x05]); // DEC B
+        cpu.register.set_8(&Register::B, 0x01);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::B), 0x00);
+        assert_eq!(flags(&cpu), (true, true, false, false));
+    }
+
+    #[test]
+    fn inc_dec_hl_memory() {
+        let mut cpu = cpu_with(&[0x34, 0x35, 0x35]); // INC (HL), DEC (HL), DEC (HL)
+        cpu.register.set_16(&Register::HL, 0xC100);
+        cpu.bus.write(0xC100, 0xFF);
+        step(&mut cpu);
+        assert_eq!(cpu.bus.read(0xC100), 0x00);
+        assert_eq!(flags(&cpu), (true, false, true, false));
+        step(&mut cpu);
+        assert_eq!(cpu.bus.read(0xC100), 0xFF);
+        assert_eq!(flags(&cpu), (false, true, true, false));
+        step(&mut cpu);
+        assert_eq!(cpu.bus.read(0xC100), 0xFE);
+        assert_eq!(flags(&cpu), (false, true, false, false));
+    }
+
+    #[test]
+    fn ld_through_hl_operand() {
+        let mut cpu = cpu_with(&[0x36, 0x5A, 0x46, 0x70]); // LD (HL), $5A ; LD B, (HL) ; LD (HL), B
+        cpu.register.set_16(&Register::HL, 0xC100);
+        step(&mut cpu);
+        assert_eq!(cpu.bus.read(0xC100), 0x5A);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::B), 0x5A);
+        cpu.register.set_8(&Register::B, 0x11);
+        step(&mut cpu);
+        assert_eq!(cpu.bus.read(0xC100), 0x11);
+    }
+
+    // 8-bit ALU
+
+    #[test]
+    fn add_a_r8_half_carry_and_carry() {
+        let mut cpu = cpu_with(&[0x80]); // ADD A, B
+        cpu.register.set_8(&Register::A, 0x8F);
+        cpu.register.set_8(&Register::B, 0x81);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x10);
+        assert_eq!(flags(&cpu), (false, false, true, true));
+    }
+
+    #[test]
+    fn add_a_n8_zero_result() {
+        let mut cpu = cpu_with(&[0xC6, 0x01]); // ADD A, $01
+        cpu.register.set_8(&Register::A, 0xFF);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, false, true, true));
+    }
+
+    #[test]
+    fn adc_a_includes_carry_in_half_carry() {
+        let mut cpu = cpu_with(&[0xCE, 0x0F]); // ADC A, $0F
+        cpu.register.set_8(&Register::A, 0xF0);
+        set_flags(&mut cpu, false, false, false, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, false, true, true));
+    }
+
+    #[test]
+    fn adc_a_hl() {
+        let mut cpu = cpu_with(&[0x8E]); // ADC A, (HL)
+        cpu.register.set_16(&Register::HL, 0xC100);
+        cpu.bus.write(0xC100, 0x01);
+        cpu.register.set_8(&Register::A, 0x0E);
+        set_flags(&mut cpu, false, false, false, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x10);
+        assert_eq!(flags(&cpu), (false, false, true, false));
+    }
+
+    #[test]
+    fn sub_r8_borrow() {
+        let mut cpu = cpu_with(&[0x90]); // SUB A, B
+        cpu.register.set_8(&Register::A, 0x10);
+        cpu.register.set_8(&Register::B, 0x21);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0xEF);
+        assert_eq!(flags(&cpu), (false, true, true, true));
+    }
+
+    #[test]
+    fn sub_a_a_is_zero() {
+        let mut cpu = cpu_with(&[0x97]); // SUB A, A
+        cpu.register.set_8(&Register::A, 0x42);
+        set_flags(&mut cpu, false, false, true, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, true, false, false));
+    }
+
+    #[test]
+    fn sbc_a_includes_carry_in_borrow() {
+        let mut cpu = cpu_with(&[0xDE, 0x0F]); // SBC A, $0F
+        cpu.register.set_8(&Register::A, 0x10);
+        set_flags(&mut cpu, false, false, false, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, true, true, false));
+    }
+
+    #[test]
+    fn sbc_a_r8_full_borrow_with_carry() {
+        let mut cpu = cpu_with(&[0x98]); // SBC A, B
+        cpu.register.set_8(&Register::A, 0x00);
+        cpu.register.set_8(&Register::B, 0xFF);
+        set_flags(&mut cpu, false, false, false, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, true, true, true));
+    }
+
+    #[test]
+    fn and_sets_half_carry() {
+        let mut cpu = cpu_with(&[0xE6, 0x0F]); // AND A, $0F
+        cpu.register.set_8(&Register::A, 0xF0);
+        set_flags(&mut cpu, false, true, false, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, false, true, false));
+    }
+
+    #[test]
+    fn xor_a_a_clears_everything() {
+        let mut cpu = cpu_with(&[0xAF]); // XOR A, A
+        cpu.register.set_8(&Register::A, 0x5A);
+        set_flags(&mut cpu, false, true, true, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, false, false, false));
+    }
+
+    #[test]
+    fn xor_r8_nonzero() {
+        let mut cpu = cpu_with(&[0xA9]); // XOR A, C
+        cpu.register.set_8(&Register::A, 0xF0);
+        cpu.register.set_8(&Register::C, 0x0F);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0xFF);
+        assert_eq!(flags(&cpu), (false, false, false, false));
+    }
+
+    #[test]
+    fn or_clears_carry() {
+        let mut cpu = cpu_with(&[0xB0]); // OR A, B
+        cpu.register.set_8(&Register::A, 0x00);
+        cpu.register.set_8(&Register::B, 0x00);
+        set_flags(&mut cpu, false, true, true, true);
+        step(&mut cpu);
+        assert_eq!(flags(&cpu), (true, false, false, false));
+    }
+
+    #[test]
+    fn cp_leaves_a_untouched() {
+        let mut cpu = cpu_with(&[0xB8, 0xFE, 0x3C]); // CP A, B ; CP A, $3C
+        cpu.register.set_8(&Register::A, 0x3C);
+        cpu.register.set_8(&Register::B, 0x2F);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x3C);
+        assert_eq!(flags(&cpu), (false, true, true, false));
+        step(&mut cpu);
+        assert_eq!(flags(&cpu), (true, true, false, false));
+    }
+
+    #[test]
+    fn cp_a_hl_borrow() {
+        let mut cpu = cpu_with(&[0xBE]); // CP A, (HL)
+        cpu.register.set_16(&Register::HL, 0xC100);
+        cpu.bus.write(0xC100, 0x40);
+        cpu.register.set_8(&Register::A, 0x3C);
+        step(&mut cpu);
+        assert_eq!(flags(&cpu), (false, true, false, true));
+    }
+
+    #[test]
+    fn daa_after_addition() {
+        let mut cpu = cpu_with(&[0xC6, 0x38, 0x27]); // ADD A, $38 ; DAA
+        cpu.register.set_8(&Register::A, 0x45);
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x83);
+        assert_eq!(flags(&cpu), (false, false, false, false));
+    }
+
+    #[test]
+    fn daa_after_addition_with_carry() {
+        let mut cpu = cpu_with(&[0xC6, 0x50, 0x27]); // ADD A, $50 ; DAA
+        cpu.register.set_8(&Register::A, 0x50);
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (true, false, false, true));
+    }
+
+    #[test]
+    fn daa_after_subtraction() {
+        let mut cpu = cpu_with(&[0xD6, 0x38, 0x27]); // SUB A, $38 ; DAA
+        cpu.register.set_8(&Register::A, 0x45);
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x07);
+        assert_eq!(flags(&cpu), (false, true, false, false));
+    }
+
+    #[test]
+    fn cpl_scf_ccf() {
+        let mut cpu = cpu_with(&[0x2F, 0x37, 0x3F]); // CPL ; SCF ; CCF
+        cpu.register.set_8(&Register::A, 0x35);
+        set_flags(&mut cpu, true, false, false, false);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0xCA);
+        assert_eq!(flags(&cpu), (true, true, true, false));
+        step(&mut cpu);
+        assert_eq!(flags(&cpu), (true, false, false, true));
+        step(&mut cpu);
+        assert_eq!(flags(&cpu), (true, false, false, false));
+    }
+
+    // 16-bit arithmetic
+
+    #[test]
+    fn add_hl_r16_keeps_zero_flag() {
+        let mut cpu = cpu_with(&[0x09]); // ADD HL, BC
+        cpu.register.set_16(&Register::HL, 0x8FFF);
+        cpu.register.set_16(&Register::BC, 0x8001);
+        set_flags(&mut cpu, true, true, false, false);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_16(&Register::HL), 0x1000);
+        assert_eq!(flags(&cpu), (true, false, true, true));
+    }
+
+    #[test]
+    fn add_hl_sp() {
+        let mut cpu = cpu_with(&[0x39]); // ADD HL, SP
+        cpu.register.set_16(&Register::HL, 0x0100);
+        cpu.set_sp(0x0200);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_16(&Register::HL), 0x0300);
+        assert_eq!(flags(&cpu), (false, false, false, false));
+    }
+
+    #[test]
+    fn inc_dec_r16_set_no_flags() {
+        let mut cpu = cpu_with(&[0x03, 0x1B]); // INC BC ; DEC DE
+        cpu.register.set_16(&Register::BC, 0xFFFF);
+        cpu.register.set_16(&Register::DE, 0x0000);
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_16(&Register::BC), 0x0000);
+        assert_eq!(cpu.register.get_16(&Register::DE), 0xFFFF);
+        assert_eq!(flags(&cpu), (false, false, false, false));
+    }
+
+    #[test]
+    fn add_sp_e8_flags_from_low_byte() {
+        let mut cpu = cpu_with(&[0xE8, 0xFF]); // ADD SP, -1
+        cpu.set_sp(0x000F);
+        set_flags(&mut cpu, true, true, false, false);
+        step(&mut cpu);
+        assert_eq!(cpu.get_sp(), 0x000E);
+        assert_eq!(flags(&cpu), (false, false, true, true));
+    }
+
+    #[test]
+    fn ld_hl_sp_e8() {
+        let mut cpu = cpu_with(&[0xF8, 0x01]); // LD HL, SP + 1
+        cpu.set_sp(0x00FF);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_16(&Register::HL), 0x0100);
+        assert_eq!(cpu.get_sp(), 0x00FF);
+        assert_eq!(flags(&cpu), (false, false, true, true));
+    }
+
+    // Rotates on A
+
+    #[test]
+    fn rlca_clears_zero() {
+        let mut cpu = cpu_with(&[0x07]); // RLCA
+        cpu.register.set_8(&Register::A, 0x80);
+        set_flags(&mut cpu, true, true, true, false);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x01);
+        assert_eq!(flags(&cpu), (false, false, false, true));
+    }
+
+    #[test]
+    fn rla_through_carry() {
+        let mut cpu = cpu_with(&[0x17]); // RLA
+        cpu.register.set_8(&Register::A, 0x80);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x00);
+        assert_eq!(flags(&cpu), (false, false, false, true));
+    }
+
+    #[test]
+    fn rrca_and_rra() {
+        let mut cpu = cpu_with(&[0x0F, 0x1F]); // RRCA ; RRA
+        cpu.register.set_8(&Register::A, 0x01);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x80);
+        assert_eq!(flags(&cpu), (false, false, false, true));
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0xC0);
+        assert_eq!(flags(&cpu), (false, false, false, false));
+    }
+
+    // CB-prefixed
+
+    #[test]
+    fn rl_r8_zero_comes_from_result() {
+        let mut cpu = cpu_with(&[0xCB, 0x11]); // RL C
+        cpu.register.set_8(&Register::C, 0x80);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::C), 0x00);
+        assert_eq!(flags(&cpu), (true, false, false, true));
+    }
+
+    #[test]
+    fn rl_r8_shifts_in_carry() {
+        let mut cpu = cpu_with(&[0xCB, 0x11]); // RL C
+        cpu.register.set_8(&Register::C, 0x01);
+        set_flags(&mut cpu, false, false, false, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::C), 0x03);
+        assert_eq!(flags(&cpu), (false, false, false, false));
+    }
+
+    #[test]
+    fn rlc_and_rrc() {
+        let mut cpu = cpu_with(&[0xCB, 0x00, 0xCB, 0x09]); // RLC B ; RRC C
+        cpu.register.set_8(&Register::B, 0x85);
+        cpu.register.set_8(&Register::C, 0x00);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::B), 0x0B);
+        assert_eq!(flags(&cpu), (false, false, false, true));
+        step(&mut cpu);
+        assert_eq!(flags(&cpu), (true, false, false, false));
+    }
+
+    #[test]
+    fn rr_hl_memory() {
+        let mut cpu = cpu_with(&[0xCB, 0x1E]); // RR (HL)
+        cpu.register.set_16(&Register::HL, 0xC100);
+        cpu.bus.write(0xC100, 0x01);
+        step(&mut cpu);
+        assert_eq!(cpu.bus.read(0xC100), 0x00);
+        assert_eq!(flags(&cpu), (true, false, false, true));
+    }
+
+    #[test]
+    fn sla_sra_srl() {
+        let mut cpu = cpu_with(&[0xCB, 0x20, 0xCB, 0x29, 0xCB, 0x3A]); // SLA B ; SRA C ; SRL D
+        cpu.register.set_8(&Register::B, 0x80);
+        cpu.register.set_8(&Register::C, 0x81);
+        cpu.register.set_8(&Register::D, 0x01);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::B), 0x00);
+        assert_eq!(flags(&cpu), (true, false, false, true));
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::C), 0xC0);
+        assert_eq!(flags(&cpu), (false, false, false, true));
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::D), 0x00);
+        assert_eq!(flags(&cpu), (true, false, false, true));
+    }
+
+    #[test]
+    fn swap_clears_carry() {
+        let mut cpu = cpu_with(&[0xCB, 0x37]); // SWAP A
+        cpu.register.set_8(&Register::A, 0xF1);
+        set_flags(&mut cpu, true, true, true, true);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x1F);
+        assert_eq!(flags(&cpu), (false, false, false, false));
+    }
+
+    #[test]
+    fn bit_sets_half_carry_and_keeps_carry() {
+        let mut cpu = cpu_with(&[0xCB, 0x7C, 0xCB, 0x7C]); // BIT 7, H ; BIT 7, H
+        cpu.register.set_8(&Register::H, 0x7F);
+        set_flags(&mut cpu, false, true, false, true);
+        step(&mut cpu);
+        assert_eq!(flags(&cpu), (true, false, true, true));
+        cpu.register.set_8(&Register::H, 0x80);
+        step(&mut cpu);
+        assert_eq!(flags(&cpu), (false, false, true, true));
+    }
+
+    #[test]
+    fn res_and_set_leave_flags() {
+        let mut cpu = cpu_with(&[0xCB, 0x86, 0xCB, 0xC7]); // RES 0, (HL) ; SET 0, A
+        cpu.register.set_16(&Register::HL, 0xC100);
+        cpu.bus.write(0xC100, 0xFF);
+        set_flags(&mut cpu, true, true, true, true);
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.bus.read(0xC100), 0xFE);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x01);
+        assert_eq!(flags(&cpu), (true, true, true, true));
+    }
+
+    // Stack and 16-bit loads
+
+    #[test]
+    fn ld_sp_n16_and_inc_dec_sp() {
+        let mut cpu = cpu_with(&[0x31, 0x34, 0x12, 0x33, 0x3B, 0x3B]); // LD SP, $1234 ; INC SP ; DEC SP ; DEC SP
+        step(&mut cpu);
+        assert_eq!(cpu.get_sp(), 0x1234);
+        step(&mut cpu);
+        assert_eq!(cpu.get_sp(), 0x1235);
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.get_sp(), 0x1233);
+    }
+
+    #[test]
+    fn push_pop_af_round_trip() {
+        let mut cpu = cpu_with(&[0xF5, 0xC1]); // PUSH AF ; POP BC
+        cpu.register.set_8(&Register::A, 0xAB);
+        set_flags(&mut cpu, true, false, true, false);
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_16(&Register::BC), 0xABA0);
+        assert_eq!(cpu.get_sp(), 0xDFFE);
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn pop_af_masks_low_nibble() {
+        let mut cpu = cpu_with(&[0xC5, 0xF1]); // PUSH BC ; POP AF
+        cpu.register.set_16(&Register::BC, 0x12FF);
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.register.get_8(&Register::A), 0x12);
+        assert_eq!(cpu.register.get_8(&Register::F), 0xF0);
+        assert_eq!(flags(&cpu), (true, true, true, true));
+    }
+
+    // Timing
+
+    #[test]
+    fn fixed_instruction_cycles() {
+        let cases: [(&[u8], u8); 14] = [
+            (&[0x00], 1),             // NOP
+            (&[0x41], 1),             // LD B, C
+            (&[0x46], 2),             // LD B, (HL)
+            (&[0x36, 0x00], 3),       // LD (HL), n8
+            (&[0x34], 3),             // INC (HL)
+            (&[0x86], 2),             // ADD A, (HL)
+            (&[0x01, 0x00, 0x00], 3), // LD BC, n16
+            (&[0x08, 0x00, 0xC2], 5), // LD (a16), SP
+            (&[0xC5], 4),             // PUSH BC
+            (&[0xC1], 3),             // POP BC
+            (&[0xE8, 0x01], 4),       // ADD SP, e8
+            (&[0xCD, 0x00, 0xC2], 6), // CALL a16
+            (&[0xC9], 4),             // RET
+            (&[0xFF], 4),             // RST $38
+        ];
+        for (program, cycles) in cases {
+            let mut cpu = cpu_with(program);
+            cpu.register.set_16(&Register::HL, 0xC100);
+            assert_eq!(step(&mut cpu), cycles, "opcode {:#04X}", program[0]);
+        }
+    }
+
+    #[test]
+    fn conditional_branch_cycles() {
+        // (opcode bytes, cycles taken, cycles not taken) with Z set so NZ is not taken and Z is
+        let cases: [(&[u8], u8, u8); 4] = [
+            (&[0x28, 0x00], 3, 2),       // JR Z, e8
+            (&[0xCA, 0x00, 0xC2], 4, 3), // JP Z, a16
+            (&[0xCC, 0x00, 0xC2], 6, 3), // CALL Z, a16
+            (&[0xC8], 5, 2),             // RET Z
+        ];
+        for (program, taken, not_taken) in cases {
+            let mut cpu = cpu_with(program);
+            set_flags(&mut cpu, true, false, false, false);
+            assert_eq!(step(&mut cpu), taken, "opcode {:#04X}", program[0]);
+
+            let mut cpu = cpu_with(program);
+            set_flags(&mut cpu, false, false, false, false);
+            assert_eq!(step(&mut cpu), not_taken, "opcode {:#04X}", program[0]);
+        }
+    }
+
+    #[test]
+    fn cb_cycles() {
+        let cases: [(&[u8], u8); 4] = [
+            (&[0xCB, 0x37], 2), // SWAP A
+            (&[0xCB, 0x36], 4), // SWAP (HL)
+            (&[0xCB, 0x46], 3), // BIT 0, (HL)
+            (&[0xCB, 0x86], 4), // RES 0, (HL)
+        ];
+        for (program, cycles) in cases {
+            let mut cpu = cpu_with(program);
+            cpu.register.set_16(&Register::HL, 0xC100);
+            assert_eq!(step(&mut cpu), cycles, "CB opcode {:#04X}", program[1]);
+        }
+    }
+
+    #[test]
+    fn cpu_accumulates_cycles() {
+        let mut cpu = cpu_with(&[0x00, 0xC5, 0x76]); // NOP ; PUSH BC ; HALT
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.get_cycles(), 6);
+        // Halted with nothing pending: the CPU idles one M-cycle at a time
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.get_cycles(), 7);
+    }
+}
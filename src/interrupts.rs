@@ -0,0 +1,45 @@
+// Interrupt sources, in priority order. Each has a bit in IE (0xFFFF) and IF (0xFF0F)
+// and a fixed handler address the CPU jumps to when servicing it.
+//
+//  Bit | Interrupt | Vector
+//  0   | VBlank    | 0x40
+//  1   | STAT      | 0x48
+//  2   | Timer     | 0x50
+//  3   | Serial    | 0x58
+//  4   | Joypad    | 0x60
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interrupt {
+    VBlank, Stat, Timer, Serial, Joypad
+}
+
+// Highest priority first
+pub const INTERRUPTS: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::Stat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+impl Interrupt {
+    pub fn bit(&self) -> u8 {
+        match self {
+            Interrupt::VBlank => 1 << 0,
+            Interrupt::Stat => 1 << 1,
+            Interrupt::Timer => 1 << 2,
+            Interrupt::Serial => 1 << 3,
+            Interrupt::Joypad => 1 << 4,
+        }
+    }
+
+    pub fn vector(&self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::Stat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// Joypad, read through P1 at 0xFF00.
+//
+//  Bit 5 | Select action buttons (0 = selected)
+//  Bit 4 | Select direction buttons (0 = selected)
+//  Bit 3 | Down  / Start  (0 = pressed)
+//  Bit 2 | Up    / Select
+//  Bit 1 | Left  / B
+//  Bit 0 | Right / A
+//
+// The Joypad interrupt is requested when one of bits 3-0 goes from high to low.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    Right, Left, Up, Down, A, B, Select, Start
+}
+
+impl Button {
+    pub fn bit(&self) -> u8 {
+        // Position in the button state mask: directions in the low nibble, actions in the high nibble
+        match self {
+            Button::Right => 1 << 0,
+            Button::Left => 1 << 1,
+            Button::Up => 1 << 2,
+            Button::Down => 1 << 3,
+            Button::A => 1 << 4,
+            Button::B => 1 << 5,
+            Button::Select => 1 << 6,
+            Button::Start => 1 << 7,
+        }
+    }
+}
+
+pub struct Joypad {
+    // Bits 5-4 of P1
+    select: u8,
+    // One bit per button as given by Button::bit, set while held
+    pressed: u8,
+    interrupt: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self { select: 0, pressed: 0, interrupt: false }
+    }
+
+    #[cfg(test)]
+    pub fn press(&mut self, button: Button) {
+        self.set_state(self.pressed | button.bit());
+    }
+
+    #[cfg(test)]
+    pub fn release(&mut self, button: Button) {
+        self.set_state(self.pressed & !button.bit());
+    }
+
+    pub fn set_state(&mut self, pressed: u8) {
+        // Replaces the state of every button at once, e.g. once per frame from a frontend or a script
+        let before: u8 = self.lines();
+        self.pressed = pressed;
+        self.check_falling_edge(before);
+    }
+
+    #[cfg(test)]
+    pub fn state(&self) -> u8 {
+        self.pressed
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    pub fn write(&mut self, data: u8) {
+        // Only the select bits are writable
+        let before: u8 = self.lines();
+        self.select = data & 0x30;
+        self.check_falling_edge(before);
+    }
+
+    pub fn take_interrupt(&mut self) -> bool {
+        // True once for every high-to-low transition since the last call
+        let interrupt: bool = self.interrupt;
+        self.interrupt = false;
+        interrupt
+    }
+
+    fn lines(&self) -> u8 {
+        // Bits 3-0 as the CPU sees them, pulled low by pressed buttons in the selected groups
+        let mut lines: u8 = 0x0F;
+        if self.select & (1 << 4) == 0 {
+            lines &= !(self.pressed & 0x0F);
+        }
+        if self.select & (1 << 5) == 0 {
+            lines &= !(self.pressed >> 4);
+        }
+        lines
+    }
+
+    fn check_falling_edge(&mut self, before: u8) {
+        if before & !self.lines() != 0 {
+            self.interrupt = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_pressed_reads_high() {
+        let mut joypad = Joypad::new();
+        assert_eq!(joypad.read(), 0xCF);
+        joypad.write(0x30);
+        assert_eq!(joypad.read(), 0xFF);
+    }
+
+    #[test]
+    fn select_bits_choose_the_button_group() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Down);
+        joypad.press(Button::A);
+
+        joypad.write(0x20); // Directions
+        assert_eq!(joypad.read(), 0xE7);
+        joypad.write(0x10); // Actions
+        assert_eq!(joypad.read(), 0xDE);
+        joypad.write(0x30);
+        assert_eq!(joypad.read(), 0xFF);
+    }
+
+    #[test]
+    fn release_and_state_mask() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x10);
+        joypad.set_state(Button::Start.bit() | Button::B.bit());
+        assert_eq!(joypad.read() & 0x0F, 0x05);
+        joypad.release(Button::Start);
+        assert_eq!(joypad.read() & 0x0F, 0x0D);
+        assert_eq!(joypad.state(), Button::B.bit());
+    }
+
+    #[test]
+    fn interrupt_on_high_to_low_transition() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x20);
+        // Actions are not selected, so pressing one changes nothing
+        joypad.press(Button::Start);
+        assert!(!joypad.take_interrupt());
+
+        joypad.press(Button::Left);
+        assert!(joypad.take_interrupt());
+        assert!(!joypad.take_interrupt());
+
+        // Releasing is a low-to-high transition
+        joypad.release(Button::Left);
+        assert!(!joypad.take_interrupt());
+
+        // Selecting a group with a held button pulls its line low
+        joypad.write(0x10);
+        assert!(joypad.take_interrupt());
+    }
+}
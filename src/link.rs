@@ -0,0 +1,374 @@
+// Link cables between two consoles.
+//
+// SocketLink joins two emulator processes over a local TCP or Unix socket. Every message is two bytes, a tag and the data byte:
+//  CLOCK | Sent by the internal-clock side when its transfer ends, carries its SB
+//  REPLY | The external-clock side's answer, carries its SB from before the transfer
+//
+// The internal-clock side blocks until the reply arrives. The external-clock side only checks the
+// socket every few M-cycles, its transfer simply stays pending until a CLOCK message comes in.
+//
+// LinkedConsoles runs two consoles in one process, wired by a LocalLink and stepped in lockstep,
+// so two-player sessions replay exactly the same way every time. Both are only built for tests.
+
+#[cfg(test)]
+use std::cell::RefCell;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(test)]
+use std::rc::Rc;
+use std::time::Duration;
+
+#[cfg(test)]
+use crate::cpu::CPU;
+use crate::serial::Link;
+
+const CLOCK: u8 = 0x01;
+const REPLY: u8 = 0x02;
+// M-cycles between checks of the socket on the external-clock side
+const POLL_INTERVAL: u16 = 64;
+// Default for the longest wait for the other side before the transfer is treated as unconnected
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+trait Stream: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+pub struct SocketLink {
+    stream: Box<dyn Stream>,
+    // Bytes of a message that has only partly arrived
+    pending: Vec<u8>,
+    countdown: u16,
+    // Set after an I/O error, the link then behaves as if nothing was connected
+    closed: bool,
+    reply_timeout: Duration,
+}
+
+impl SocketLink {
+    fn new(stream: Box<dyn Stream>) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Self { stream, pending: Vec::with_capacity(2), countdown: 0, closed: false, reply_timeout: REPLY_TIMEOUT })
+    }
+
+    pub fn listen_tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        // Waits for the other emulator to connect
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        stream.set_nodelay(true)?;
+        Self::new(Box::new(stream))
+    }
+
+    pub fn connect_tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream: TcpStream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Self::new(Box::new(stream))
+    }
+
+    #[cfg(unix)]
+    pub fn listen_unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (stream, _) = UnixListener::bind(path)?.accept()?;
+        Self::new(Box::new(stream))
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(Box::new(UnixStream::connect(path)?))
+    }
+
+    pub fn set_reply_timeout(&mut self, timeout: Duration) {
+        self.reply_timeout = timeout;
+    }
+
+    fn send(&mut self, tag: u8, byte: u8) {
+        if self.closed {
+            return;
+        }
+        // write_all on a nonblocking socket can stop halfway, so switch to blocking for the write
+        let result: io::Result<()> = self.stream.set_nonblocking(false)
+            .and_then(|_| self.stream.write_all(&[tag, byte]))
+            .and_then(|_| self.stream.set_nonblocking(true));
+        if result.is_err() {
+            self.closed = true;
+        }
+    }
+
+    fn receive(&mut self) -> Option<(u8, u8)> {
+        // Reads whatever has arrived and returns the message once both of its bytes are in
+        while !self.closed && self.pending.len() < 2 {
+            let mut buffer: [u8; 2] = [0; 2];
+            match self.stream.read(&mut buffer[..2 - self.pending.len()]) {
+                Ok(0) => self.closed = true,
+                Ok(count) => self.pending.extend_from_slice(&buffer[..count]),
+                Err(error) if error.kind() == ErrorKind::Interrupted => {},
+                Err(error) if error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut => return None,
+                Err(_) => self.closed = true,
+            }
+        }
+        if self.closed {
+            return None;
+        }
+
+        let message: (u8, u8) = (self.pending[0], self.pending[1]);
+        self.pending.clear();
+        Some(message)
+    }
+}
+
+impl Link for SocketLink {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.send(CLOCK, byte);
+
+        let blocking: io::Result<()> = self.stream.set_nonblocking(false)
+            .and_then(|_| self.stream.set_read_timeout(Some(self.reply_timeout)));
+        if blocking.is_err() {
+            self.closed = true;
+        }
+
+        let mut reply: u8 = 0xFF;
+        while let Some((tag, data)) = self.receive() {
+            // A CLOCK here means both sides used the internal clock at once, each gets the other's byte
+            if tag == REPLY || tag == CLOCK {
+                reply = data;
+                break;
+            }
+        }
+
+        if !self.closed && self.stream.set_nonblocking(true).is_err() {
+            self.closed = true;
+        }
+        reply
+    }
+
+    fn poll(&mut self, reply: u8) -> Option<u8> {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return None;
+        }
+        self.countdown = POLL_INTERVAL;
+
+        match self.receive() {
+            Some((CLOCK, data)) => {
+                self.send(REPLY, reply);
+                Some(data)
+            },
+            // A late REPLY to a transfer that already timed out
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+struct Wire {
+    // SB of each end as of its last M-cycle
+    sb: [u8; 2],
+    // Byte clocked into each end by the other one, not picked up yet
+    clocked: [Option<u8>; 2],
+}
+
+#[cfg(test)]
+pub struct LocalLink {
+    wire: Rc<RefCell<Wire>>,
+    end: usize,
+}
+
+#[cfg(test)]
+impl LocalLink {
+    pub fn pair() -> (Self, Self) {
+        // Both ends of one cable
+        let wire: Rc<RefCell<Wire>> = Rc::new(RefCell::new(Wire { sb: [0xFF; 2], clocked: [None; 2] }));
+        (Self { wire: wire.clone(), end: 0 }, Self { wire, end: 1 })
+    }
+}
+
+#[cfg(test)]
+impl Link for LocalLink {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let other: usize = 1 - self.end;
+        wire.clocked[other] = Some(byte);
+        wire.sb[other]
+    }
+
+    fn poll(&mut self, reply: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        wire.sb[self.end] = reply;
+        wire.clocked[self.end].take()
+    }
+}
+
+#[cfg(test)]
+pub struct LinkedConsoles {
+    pub left: CPU,
+    pub right: CPU,
+}
+
+#[cfg(test)]
+impl LinkedConsoles {
+    pub fn new(mut left: CPU, mut right: CPU) -> Self {
+        let (left_end, right_end): (LocalLink, LocalLink) = LocalLink::pair();
+        left.bus.serial.connect(Box::new(left_end));
+        right.bus.serial.connect(Box::new(right_end));
+        Self { left, right }
+    }
+
+    pub fn step(&mut self) {
+        // Runs one instruction on whichever console is behind, so neither gets more than an instruction ahead
+        if self.left.get_cycles() <= self.right.get_cycles() {
+            self.left.step();
+        } else {
+            self.right.step();
+        }
+    }
+
+    pub fn run(&mut self, cycles: u64) {
+        // Runs both consoles for at least `cycles` M-cycles
+        let target: u64 = self.left.get_cycles().min(self.right.get_cycles()) + cycles;
+        while self.left.get_cycles().min(self.right.get_cycles()) < target {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::cpu_with;
+    use crate::registers::Register;
+    use std::thread;
+
+    fn tcp_pair() -> (SocketLink, TcpStream) {
+        // A link and the raw socket of its other end, which is turned into a link on the thread that uses it
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (SocketLink::new(Box::new(stream)).unwrap(), client)
+    }
+
+    fn poll_until_clocked(link: &mut SocketLink, reply: u8) -> u8 {
+        loop {
+            if let Some(byte) = link.poll(reply) {
+                return byte;
+            }
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn internal_clock_side_waits_for_the_reply() {
+        let (mut master, slave) = tcp_pair();
+        let slave = thread::spawn(move || poll_until_clocked(&mut SocketLink::new(Box::new(slave)).unwrap(), 0x55));
+
+        assert_eq!(master.exchange(0xAA), 0x55);
+        assert_eq!(slave.join().unwrap(), 0xAA);
+    }
+
+    #[test]
+    fn unanswered_exchange_times_out_as_unconnected() {
+        let (mut master, _slave) = tcp_pair();
+        master.set_reply_timeout(Duration::from_millis(10));
+        assert_eq!(master.exchange(0x12), 0xFF);
+    }
+
+    #[test]
+    fn closed_link_reads_as_unconnected() {
+        let (mut master, slave) = tcp_pair();
+        drop(slave);
+        assert_eq!(master.exchange(0x12), 0xFF);
+        assert_eq!(master.poll(0x00), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_link() {
+        let path = std::env::temp_dir().join(format!("dmg-01-link-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener_path = path.clone();
+        let listener = thread::spawn(move || {
+            let mut link = SocketLink::listen_unix(&listener_path).unwrap();
+            poll_until_clocked(&mut link, 0x34)
+        });
+
+        let mut link = loop {
+            match SocketLink::connect_unix(&path) {
+                Ok(link) => break link,
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        };
+        assert_eq!(link.exchange(0x12), 0x34);
+        assert_eq!(listener.join().unwrap(), 0x12);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // LD A, data ; LDH (0x01), A ; LD A, control ; LDH (0x02), A
+    // wait: LDH A, (0x02) ; BIT 7, A ; JR NZ, wait ; LDH A, (0x01) ; done: JR done
+    fn transfer_program(data: u8, control: u8) -> [u8; 18] {
+        [0x3E, data, 0xE0, 0x01, 0x3E, control, 0xE0, 0x02, 0xF0, 0x02, 0xCB, 0x7F, 0x20, 0xFA, 0xF0, 0x01, 0x18, 0xFE]
+    }
+
+    fn linked_pair() -> LinkedConsoles {
+        LinkedConsoles::new(cpu_with(&transfer_program(0x42, 0x81)), cpu_with(&transfer_program(0x99, 0x80)))
+    }
+
+    #[test]
+    fn local_link_swaps_bytes_between_consoles() {
+        let mut consoles = linked_pair();
+        consoles.run(2000);
+
+        assert_eq!(consoles.left.register.get_8(&Register::A), 0x99);
+        assert_eq!(consoles.right.register.get_8(&Register::A), 0x42);
+        assert_eq!(consoles.left.bus.serial.output(), &[0x42]);
+        assert_eq!(consoles.right.bus.serial.output(), &[0x99]);
+    }
+
+    #[test]
+    fn slave_receives_the_byte_as_it_is_clocked() {
+        // The master writes SC in the instruction starting at M-cycle 7, so its byte goes out 1024 M-cycles later.
+        // Stepped in lockstep, the slave has to pick it up in that same M-cycle, not an instruction or more later.
+        let mut consoles = linked_pair();
+        let mut done: [Option<u64>; 2] = [None; 2];
+        while done.contains(&None) {
+            consoles.step();
+            let cycles: [u64; 2] = [consoles.left.get_cycles(), consoles.right.get_cycles()];
+            assert!(cycles[0].abs_diff(cycles[1]) <= 6, "{:?}", cycles);
+
+            for (i, console) in [&consoles.left, &consoles.right].into_iter().enumerate() {
+                if done[i].is_none() && cycles[i] > 10 && console.bus.serial.read(0xFF02) & 0x80 == 0 {
+                    done[i] = Some(cycles[i]);
+                }
+            }
+        }
+        assert_eq!(done, [Some(7 + 1024), Some(7 + 1024)]);
+        assert_eq!(consoles.right.bus.serial.read(0xFF01), 0x42);
+    }
+
+    #[test]
+    fn consoles_stay_in_lockstep() {
+        let mut consoles = linked_pair();
+        for _ in 0..500 {
+            consoles.step();
+            assert!(consoles.left.get_cycles().abs_diff(consoles.right.get_cycles()) <= 6);
+        }
+    }
+}
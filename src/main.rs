@@ -2,15 +2,54 @@ mod cpu;
 mod bus;
 mod instructions;
 mod registers;
+mod interrupts;
+mod timer;
+mod ppu;
+mod dma;
+mod joypad;
+mod serial;
+mod link;
+mod cartridge;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
 
 fn main() {
 
-    let mut cpu = cpu::CPU::new();
+    let args: Vec<String> = std::env::args().collect();
+
+    // --fifo draws with the pixel FIFO renderer, for games that change registers in the middle of a line
+    let mut cpu = if has_flag(&args, "--fifo") {
+        cpu::CPU::with_renderer(ppu::Renderer::Fifo)
+    } else {
+        cpu::CPU::new()
+    };
 
     if cpu.verify(){
         println!("CPU Initialized")
     }
 
+    // --cycle-accurate ticks the rest of the system on every bus access instead of after each instruction
+    cpu.set_cycle_accurate(has_flag(&args, "--cycle-accurate"));
+
+    // --no-access-locking lets the CPU reach VRAM and OAM while the PPU is using them, for debugging
+    cpu.bus.set_ppu_access_locking(!has_flag(&args, "--no-access-locking"));
+
+    // Link cable to another instance: --link-listen <addr> waits for it, --link-connect <addr> joins it.
+    // An address of the form unix:<path> uses a Unix socket instead of TCP.
+    let link = flag_value(&args, "--link-listen").map(|addr| open_link(addr, true))
+        .or_else(|| flag_value(&args, "--link-connect").map(|addr| open_link(addr, false)));
+    if let Some(link) = link {
+        let mut link = link.expect("Could not open the link cable");
+        // --link-timeout <ms> sets how long a transfer waits for the other side before it reads as unconnected
+        if let Some(timeout) = flag_value(&args, "--link-timeout") {
+            let timeout: u64 = timeout.parse().expect("--link-timeout takes a number of milliseconds");
+            link.set_reply_timeout(std::time::Duration::from_millis(timeout));
+        }
+        cpu.bus.serial.connect(Box::new(link));
+    }
+
     let boot_rom = include_bytes!("../bin/dmg_boot.bin");
     let game_rom = include_bytes!("../bin/tetris.gb");
     // Loads bootrom from 0x000-0x100
@@ -18,9 +57,94 @@ fn main() {
         //cpu.bus.write(i as u16, *byte);
         cpu.bus.boot_rom[i] = *byte;
     } 
-    for (i, byte) in game_rom.iter().enumerate() {
-        cpu.bus.cartridge[i] = *byte;
-    } 
 
-    cpu.boot()
-}
\ No newline at end of file
+    let cartridge = cartridge::Cartridge::new(game_rom.to_vec()).expect("Could not load the cartridge");
+    let header = &cartridge.header;
+    println!("Cartridge: {} v{}, licensee {:?}", header.title, header.version, header.licensee);
+    if header.cgb_flag == 0xC0 {
+        println!("Warning: made only for the Game Boy Color");
+    }
+    if header.sgb_flag == 0x03 {
+        println!("Super Game Boy features are not emulated");
+    }
+    if !cartridge.global_checksum_valid() {
+        println!("Warning: global checksum does not match");
+    }
+    cpu.bus.cartridge = Some(cartridge);
+
+    // --print-serial echoes every byte sent over the serial port, which is how Blargg's test ROMs report their results
+    let print_serial: bool = has_flag(&args, "--print-serial");
+
+    // Input comes from stdin, one line per change naming every button now held, e.g. "a right".
+    // An empty line releases them all.
+    let (input_sender, input) = std::sync::mpsc::channel::<u8>();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            let held: u8 = line.split_whitespace().filter_map(button_from_name).fold(0, |held, button| held | button.bit());
+            if input_sender.send(held).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Keeps to the DMG's speed of 2^20 M-cycles per second by sleeping off any lead at the end of each frame
+    let start = std::time::Instant::now();
+    let mut rumbling: bool = false;
+    cpu.boot(|cpu| {
+        if let Some(held) = input.try_iter().last() {
+            cpu.bus.joypad.set_state(held);
+        }
+        let sent: Vec<u8> = cpu.bus.serial.take_output();
+        if print_serial && !sent.is_empty() {
+            print!("{}", String::from_utf8_lossy(&sent));
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+        }
+        // Rumble carts can toggle the motor many times a frame to vary its strength, so only report where it ended up
+        if let Some(cartridge) = &mut cpu.bus.cartridge {
+            if !cartridge.take_motor_events().is_empty() && cartridge.motor_on() != rumbling {
+                rumbling = cartridge.motor_on();
+                println!("Rumble {}", if rumbling { "on" } else { "off" });
+            }
+        }
+
+        let emulated = std::time::Duration::from_secs_f64(cpu.get_cycles() as f64 / (1 << 20) as f64);
+        if let Some(lead) = emulated.checked_sub(start.elapsed()) {
+            std::thread::sleep(lead);
+        }
+    })
+}
+
+fn has_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    // The argument following a flag, e.g. the address in --link-listen <addr>
+    let index: usize = args.iter().position(|arg| arg == name)?;
+    args.get(index + 1).map(String::as_str)
+}
+
+fn open_link(addr: &str, listen: bool) -> std::io::Result<link::SocketLink> {
+    #[cfg(unix)]
+    if let Some(path) = addr.strip_prefix("unix:") {
+        return if listen { link::SocketLink::listen_unix(path) } else { link::SocketLink::connect_unix(path) };
+    }
+    if listen { link::SocketLink::listen_tcp(addr) } else { link::SocketLink::connect_tcp(addr) }
+}
+
+fn button_from_name(name: &str) -> Option<joypad::Button> {
+    match name.to_ascii_lowercase().as_str() {
+        "right" => Some(joypad::Button::Right),
+        "left" => Some(joypad::Button::Left),
+        "up" => Some(joypad::Button::Up),
+        "down" => Some(joypad::Button::Down),
+        "a" => Some(joypad::Button::A),
+        "b" => Some(joypad::Button::B),
+        "select" => Some(joypad::Button::Select),
+        "start" => Some(joypad::Button::Start),
+        _ => {
+            println!("Unknown button: {}", name);
+            None
+        },
+    }
+}
@@ -0,0 +1,160 @@
+// MBC1 bank controller.
+//
+//  0x0000-0x1FFF | RAM enable, 0x_A in the low nibble enables
+//  0x2000-0x3FFF | BANK1, 5-bit ROM bank for 0x4000-0x7FFF, 0 selects 1
+//  0x4000-0x5FFF | BANK2, 2 bits: upper ROM bank bits, or the RAM bank in mode 1
+//  0x6000-0x7FFF | Mode, 1 also applies BANK2 to 0x0000-0x3FFF and RAM
+//
+// MBC1M multicarts wire only 4 bits of BANK1, so BANK2 starts at bank bit 4 instead of 5.
+
+pub struct Mbc1 {
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(multicart: bool) -> Self {
+        Self { ram_enabled: false, bank1: 1, bank2: 0, mode: false, multicart }
+    }
+
+    pub fn read(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => {
+                let bank: usize = if self.mode { self.upper_bank() } else { 0 };
+                rom[(bank * 0x4000 + addr as usize) % rom.len()]
+            },
+            0x4000..=0x7FFF => {
+                let lower: usize = if self.multicart { self.bank1 & 0x0F } else { self.bank1 } as usize;
+                let bank: usize = self.upper_bank() | lower;
+                rom[(bank * 0x4000 + (addr - 0x4000) as usize) % rom.len()]
+            },
+            _ => match self.ram_offset(ram, addr) {
+                Some(offset) => ram[offset],
+                None => 0xFF,
+            },
+        }
+    }
+
+    pub fn write(&mut self, ram: &mut [u8], addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // The zero check sees all 5 bits, so banks 0x20, 0x40 and 0x60 can't be reached through 0x4000
+                self.bank1 = data & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.bank2 = data & 0x03,
+            0x6000..=0x7FFF => self.mode = data & 0x01 != 0,
+            _ => {
+                if let Some(offset) = self.ram_offset(ram, addr) {
+                    ram[offset] = data;
+                }
+            },
+        }
+    }
+
+    fn upper_bank(&self) -> usize {
+        (self.bank2 as usize) << if self.multicart { 4 } else { 5 }
+    }
+
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> Option<usize> {
+        if !self.ram_enabled || ram.is_empty() {
+            return None;
+        }
+        let bank: usize = if self.mode { self.bank2 as usize } else { 0 };
+        Some((bank * 0x2000 + (addr - 0xA000) as usize) % ram.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::tests::rom_with_header;
+
+    fn banked_rom(rom_code: u8, ram_code: u8) -> Vec<u8> {
+        // Every bank starts with its own number
+        let mut rom = rom_with_header(0x03, rom_code, ram_code);
+        for bank in 1..rom.len() / 0x4000 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn switches_the_rom_bank_at_0x4000() {
+        let mut cartridge = Cartridge::new(banked_rom(0x04, 0x00)).unwrap(); // 512 KiB
+        assert_eq!(cartridge.read(0x4000), 1);
+        cartridge.write(0x2000, 0x05);
+        assert_eq!(cartridge.read(0x4000), 5);
+        // Bank 0 maps to 1, and only 5 bits are kept
+        cartridge.write(0x2000, 0x00);
+        assert_eq!(cartridge.read(0x4000), 1);
+        cartridge.write(0x2000, 0xE3);
+        assert_eq!(cartridge.read(0x4000), 3);
+        // Larger than the ROM wraps around
+        cartridge.write(0x2000, 0x1F);
+        assert_eq!(cartridge.read(0x4000), 0x1F);
+    }
+
+    #[test]
+    fn upper_bits_select_large_rom_banks() {
+        let mut cartridge = Cartridge::new(banked_rom(0x06, 0x00)).unwrap(); // 2 MiB
+        cartridge.write(0x4000, 0x01);
+        cartridge.write(0x2000, 0x02);
+        assert_eq!(cartridge.read(0x4000), 0x22);
+        // 0x20 is not reachable at 0x4000 since BANK1 = 0 becomes 1
+        cartridge.write(0x2000, 0x00);
+        assert_eq!(cartridge.read(0x4000), 0x21);
+
+        // Mode 0 keeps bank 0 at 0x0000, mode 1 applies BANK2 there too
+        assert_eq!(cartridge.read(0x0000), 0x00);
+        cartridge.write(0x6000, 0x01);
+        assert_eq!(cartridge.read(0x0000), 0x20);
+    }
+
+    #[test]
+    fn ram_needs_enabling_and_banks_in_mode_1() {
+        let mut cartridge = Cartridge::new(banked_rom(0x00, 0x03)).unwrap(); // 32 KiB RAM
+        cartridge.write(0xA000, 0x11);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0xA000, 0x11);
+        cartridge.write(0x4000, 0x02);
+        assert_eq!(cartridge.read(0xA000), 0x11); // Mode 0 ignores BANK2 for RAM
+
+        cartridge.write(0x6000, 0x01);
+        assert_eq!(cartridge.read(0xA000), 0x00);
+        cartridge.write(0xA000, 0x22);
+        cartridge.write(0x4000, 0x00);
+        assert_eq!(cartridge.read(0xA000), 0x11);
+        assert_eq!(cartridge.ram()[0x4000], 0x22);
+
+        cartridge.write(0x0000, 0x00);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn multicart_is_detected_and_uses_4_bit_banks() {
+        let mut rom = banked_rom(0x05, 0x00); // 1 MiB
+        rom[0x40104..0x40134].copy_from_slice(&crate::cartridge::NINTENDO_LOGO);
+        let mut cartridge = Cartridge::new(rom).unwrap();
+
+        cartridge.write(0x4000, 0x01);
+        cartridge.write(0x2000, 0x12);
+        assert_eq!(cartridge.read(0x4000), 0x12);
+        cartridge.write(0x6000, 0x01);
+        assert_eq!(cartridge.read(0x0000), 0x10);
+
+        // Without a second logo the same ROM is a plain MBC1
+        let mut cartridge = Cartridge::new(banked_rom(0x05, 0x00)).unwrap();
+        cartridge.write(0x4000, 0x01);
+        cartridge.write(0x2000, 0x12);
+        assert_eq!(cartridge.read(0x4000), 0x32);
+    }
+}
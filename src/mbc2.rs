@@ -0,0 +1,96 @@
+// MBC2 bank controller, with 512 x 4 bits of RAM built in.
+//
+//  0x0000-0x3FFF | Address bit 8 clear: RAM enable, 0x_A in the low nibble enables
+//                | Address bit 8 set: 4-bit ROM bank for 0x4000-0x7FFF, 0 selects 1
+//  0xA000-0xBFFF | RAM, only address bits 0-8 are decoded so the 512 cells repeat across the range.
+//                | Only the low 4 bits are stored, the upper ones read as 1.
+
+pub const RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new() -> Self {
+        Self { ram_enabled: false, rom_bank: 1 }
+    }
+
+    pub fn read(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom[addr as usize],
+            0x4000..=0x7FFF => rom[(self.rom_bank as usize * 0x4000 + (addr - 0x4000) as usize) % rom.len()],
+            _ => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                0xF0 | ram[addr as usize & (RAM_SIZE - 1)]
+            },
+        }
+    }
+
+    pub fn write(&mut self, ram: &mut [u8], addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x3FFF => {
+                if addr & 0x100 == 0 {
+                    self.ram_enabled = data & 0x0F == 0x0A;
+                } else {
+                    self.rom_bank = data & 0x0F;
+                    if self.rom_bank == 0 {
+                        self.rom_bank = 1;
+                    }
+                }
+            },
+            0x4000..=0x7FFF => {},
+            _ => {
+                if self.ram_enabled {
+                    ram[addr as usize & (RAM_SIZE - 1)] = data & 0x0F;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::tests::rom_with_header;
+
+    #[test]
+    fn address_bit_8_selects_the_register() {
+        let mut rom = rom_with_header(0x06, 0x03, 0x00); // 256 KiB
+        rom[0x4000] = 0x01;
+        rom[0x0F * 0x4000] = 0x0F;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+
+        // Bit 8 clear: RAM enable, the ROM bank stays
+        cartridge.write(0x0000, 0x0F);
+        assert_eq!(cartridge.read(0x4000), 0x01);
+        cartridge.write(0x2100, 0xFF);
+        assert_eq!(cartridge.read(0x4000), 0x0F);
+        cartridge.write(0x0100, 0x00);
+        assert_eq!(cartridge.read(0x4000), 0x01);
+
+        cartridge.write(0x3E00, 0x0A);
+        cartridge.write(0xA000, 0x05);
+        assert_eq!(cartridge.read(0xA000), 0xF5);
+        cartridge.write(0x0000, 0x00);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn nibble_ram_is_mirrored() {
+        let mut cartridge = Cartridge::new(rom_with_header(0x06, 0x00, 0x00)).unwrap();
+        assert_eq!(cartridge.ram().len(), 512);
+        cartridge.write(0x0000, 0x0A);
+
+        cartridge.write(0xA1FF, 0xAB);
+        assert_eq!(cartridge.read(0xA1FF), 0xFB);
+        assert_eq!(cartridge.read(0xA3FF), 0xFB);
+        assert_eq!(cartridge.read(0xBFFF), 0xFB);
+        cartridge.write(0xB000, 0x3C);
+        assert_eq!(cartridge.read(0xA000), 0xFC);
+        assert_eq!(cartridge.ram()[0], 0x0C);
+    }
+}
@@ -0,0 +1,363 @@
+// MBC3 bank controller, with the real-time clock found on some carts.
+//
+//  0x0000-0x1FFF | RAM and RTC enable, 0x_A in the low nibble enables
+//  0x2000-0x3FFF | 7-bit ROM bank for 0x4000-0x7FFF, 0 selects 1
+//  0x4000-0x5FFF | 0x00-0x03 map a RAM bank at 0xA000, 0x08-0x0C map an RTC register there instead
+//  0x6000-0x7FFF | Writing 0 then 1 latches the clock into the readable RTC registers
+//
+// RTC registers
+//  0x08 | S  | Seconds, 0-59
+//  0x09 | M  | Minutes, 0-59
+//  0x0A | H  | Hours, 0-23
+//  0x0B | DL | Lower 8 bits of the day counter
+//  0x0C | DH | Bit 0 day counter bit 8, bit 6 halt, bit 7 day counter carry
+
+use std::time::Instant;
+
+// The clock runs at 4.194304 MHz, so one second is 2^20 M-cycles
+const CYCLES_PER_SECOND: u32 = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtcClock {
+    // Follows the host's clock
+    WallClock,
+    // Counts emulated M-cycles, so runs are repeatable
+    Cycles,
+}
+
+struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    carry: bool,
+    // S, M, H, DL and DH as of the last latch
+    latched: [u8; 5],
+
+    clock: RtcClock,
+    cycles: u32,
+    last_sync: Instant,
+}
+
+impl Rtc {
+    fn new(clock: RtcClock) -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            carry: false,
+            latched: [0; 5],
+            clock,
+            cycles: 0,
+            last_sync: Instant::now(),
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.clock != RtcClock::Cycles || self.halt {
+            return;
+        }
+        self.cycles += 1;
+        if self.cycles == CYCLES_PER_SECOND {
+            self.cycles = 0;
+            self.advance_second();
+        }
+    }
+
+    fn sync(&mut self) {
+        // Catches up with the host clock, keeping any partial second for next time
+        if self.clock != RtcClock::WallClock {
+            return;
+        }
+        let elapsed: u64 = self.last_sync.elapsed().as_secs();
+        self.last_sync += std::time::Duration::from_secs(elapsed);
+        if self.halt {
+            return;
+        }
+        for _ in 0..elapsed {
+            self.advance_second();
+        }
+    }
+
+    fn advance_second(&mut self) {
+        // Registers count within their bit width, out of range values written by software
+        // wrap to 0 without carrying into the next register
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.days += 1;
+        if self.days == 512 {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    fn latch(&mut self) {
+        self.sync();
+        self.latched = [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            (self.days >> 8) as u8 | (self.halt as u8) << 6 | (self.carry as u8) << 7,
+        ];
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        self.latched[(register - 0x08) as usize]
+    }
+
+    fn write(&mut self, register: u8, data: u8) {
+        self.sync();
+        match register {
+            0x08 => {
+                self.seconds = data & 0x3F;
+                self.cycles = 0;
+            },
+            0x09 => self.minutes = data & 0x3F,
+            0x0A => self.hours = data & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | data as u16,
+            _ => {
+                self.days = (self.days & 0xFF) | ((data & 0x01) as u16) << 8;
+                self.halt = data & (1 << 6) != 0;
+                self.carry = data & (1 << 7) != 0;
+            },
+        }
+        // Written values show up in the readable registers straight away
+        self.latch();
+    }
+}
+
+pub struct Mbc3 {
+    enabled: bool,
+    rom_bank: u8,
+    // RAM bank (0x00-0x03) or RTC register (0x08-0x0C) mapped at 0xA000
+    ram_select: u8,
+    latch_armed: bool,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(has_rtc: bool, clock: RtcClock) -> Self {
+        let rtc: Option<Rtc> = if has_rtc { Some(Rtc::new(clock)) } else { None };
+        Self { enabled: false, rom_bank: 1, ram_select: 0, latch_armed: false, rtc }
+    }
+
+    pub fn tick(&mut self) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick();
+        }
+    }
+
+    pub fn read(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom[addr as usize],
+            0x4000..=0x7FFF => rom[(self.rom_bank as usize * 0x4000 + (addr - 0x4000) as usize) % rom.len()],
+            _ => {
+                if !self.enabled {
+                    return 0xFF;
+                }
+                match (&self.rtc, self.ram_select) {
+                    (_, 0x00..=0x03) if !ram.is_empty() => ram[self.ram_offset(ram, addr)],
+                    (Some(rtc), 0x08..=0x0C) => rtc.read(self.ram_select),
+                    _ => 0xFF,
+                }
+            },
+        }
+    }
+
+    pub fn write(&mut self, ram: &mut [u8], addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.enabled = data & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = data & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.ram_select = data & 0x0F,
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    if self.latch_armed && data == 0x01 {
+                        rtc.latch();
+                    }
+                }
+                self.latch_armed = data == 0x00;
+            },
+            _ => {
+                if !self.enabled {
+                    return;
+                }
+                match (&mut self.rtc, self.ram_select) {
+                    (_, 0x00..=0x03) if !ram.is_empty() => {
+                        let offset: usize = self.ram_offset(ram, addr);
+                        ram[offset] = data;
+                    },
+                    (Some(rtc), register @ 0x08..=0x0C) => rtc.write(register, data),
+                    _ => {},
+                }
+            },
+        }
+    }
+
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> usize {
+        (self.ram_select as usize * 0x2000 + (addr - 0xA000) as usize) % ram.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::tests::rom_with_header;
+
+    fn rtc_cartridge() -> Cartridge {
+        let mut cartridge = Cartridge::with_rtc_clock(rom_with_header(0x10, 0x06, 0x03), RtcClock::Cycles).unwrap();
+        cartridge.write(0x0000, 0x0A);
+        cartridge
+    }
+
+    fn latch(cartridge: &mut Cartridge) {
+        cartridge.write(0x6000, 0x00);
+        cartridge.write(0x6000, 0x01);
+    }
+
+    fn rtc_register(cartridge: &mut Cartridge, register: u8) -> u8 {
+        cartridge.write(0x4000, register);
+        cartridge.read(0xA000)
+    }
+
+    fn set_rtc_register(cartridge: &mut Cartridge, register: u8, data: u8) {
+        cartridge.write(0x4000, register);
+        cartridge.write(0xA000, data);
+    }
+
+    fn run(cartridge: &mut Cartridge, cycles: u32) {
+        for _ in 0..cycles {
+            cartridge.tick();
+        }
+    }
+
+    #[test]
+    fn rom_and_ram_banks() {
+        let mut rom = rom_with_header(0x13, 0x06, 0x03); // 2 MiB ROM, 32 KiB RAM
+        rom[0x7F * 0x4000] = 0x7F;
+        rom[0x4000] = 0x01;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+
+        cartridge.write(0x2000, 0xFF);
+        assert_eq!(cartridge.read(0x4000), 0x7F);
+        cartridge.write(0x2000, 0x00);
+        assert_eq!(cartridge.read(0x4000), 0x01);
+
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+        cartridge.write(0x0000, 0x0A);
+        for bank in 0..4 {
+            cartridge.write(0x4000, bank);
+            cartridge.write(0xA000, bank + 0x10);
+        }
+        cartridge.write(0x4000, 0x02);
+        assert_eq!(cartridge.read(0xA000), 0x12);
+        assert_eq!(cartridge.ram()[0x6000], 0x13);
+    }
+
+    #[test]
+    fn rtc_reads_the_latched_time() {
+        let mut cartridge = rtc_cartridge();
+        run(&mut cartridge, 3 * CYCLES_PER_SECOND);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 0);
+
+        latch(&mut cartridge);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 3);
+        run(&mut cartridge, CYCLES_PER_SECOND);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 3);
+
+        // Writing 1 without a 0 before it does not latch
+        cartridge.write(0x6000, 0x01);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 3);
+        latch(&mut cartridge);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 4);
+    }
+
+    #[test]
+    fn rtc_carries_into_minutes_hours_and_days() {
+        let mut cartridge = rtc_cartridge();
+        set_rtc_register(&mut cartridge, 0x08, 59);
+        set_rtc_register(&mut cartridge, 0x09, 59);
+        set_rtc_register(&mut cartridge, 0x0A, 23);
+        set_rtc_register(&mut cartridge, 0x0B, 0xFF);
+        run(&mut cartridge, CYCLES_PER_SECOND);
+
+        latch(&mut cartridge);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 0);
+        assert_eq!(rtc_register(&mut cartridge, 0x09), 0);
+        assert_eq!(rtc_register(&mut cartridge, 0x0A), 0);
+        assert_eq!(rtc_register(&mut cartridge, 0x0B), 0x00);
+        assert_eq!(rtc_register(&mut cartridge, 0x0C), 0x01);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_carry() {
+        let mut cartridge = rtc_cartridge();
+        set_rtc_register(&mut cartridge, 0x0B, 0xFF);
+        set_rtc_register(&mut cartridge, 0x0C, 0x01);
+        set_rtc_register(&mut cartridge, 0x0A, 23);
+        set_rtc_register(&mut cartridge, 0x09, 59);
+        set_rtc_register(&mut cartridge, 0x08, 59);
+        run(&mut cartridge, CYCLES_PER_SECOND);
+
+        latch(&mut cartridge);
+        assert_eq!(rtc_register(&mut cartridge, 0x0B), 0x00);
+        assert_eq!(rtc_register(&mut cartridge, 0x0C), 0x80);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut cartridge = rtc_cartridge();
+        set_rtc_register(&mut cartridge, 0x0C, 0x40);
+        run(&mut cartridge, 2 * CYCLES_PER_SECOND);
+        latch(&mut cartridge);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 0);
+        assert_eq!(rtc_register(&mut cartridge, 0x0C), 0x40);
+
+        set_rtc_register(&mut cartridge, 0x0C, 0x00);
+        run(&mut cartridge, 2 * CYCLES_PER_SECOND);
+        latch(&mut cartridge);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 2);
+    }
+
+    #[test]
+    fn out_of_range_seconds_wrap_without_carry() {
+        let mut cartridge = rtc_cartridge();
+        set_rtc_register(&mut cartridge, 0x08, 62);
+        run(&mut cartridge, 2 * CYCLES_PER_SECOND);
+        latch(&mut cartridge);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 0);
+        assert_eq!(rtc_register(&mut cartridge, 0x09), 0);
+    }
+
+    #[test]
+    fn rtc_is_only_mapped_when_present() {
+        let mut cartridge = Cartridge::new(rom_with_header(0x11, 0x00, 0x00)).unwrap();
+        cartridge.write(0x0000, 0x0A);
+        assert_eq!(rtc_register(&mut cartridge, 0x08), 0xFF);
+    }
+}
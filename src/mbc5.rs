@@ -0,0 +1,147 @@
+// MBC5 bank controller, also used by rumble carts.
+//
+//  0x0000-0x1FFF | RAM enable, exactly 0x0A enables
+//  0x2000-0x2FFF | Lower 8 bits of the ROM bank for 0x4000-0x7FFF, bank 0 is allowed
+//  0x3000-0x3FFF | Bit 8 of the ROM bank
+//  0x4000-0x5FFF | RAM bank, 0x00-0x0F. On rumble carts bit 3 drives the motor instead.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotorEvent {
+    Started, Stopped
+}
+
+pub struct Mbc5 {
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    rumble: bool,
+    motor_on: bool,
+    // Motor changes since the host last asked
+    motor_events: Vec<MotorEvent>,
+}
+
+impl Mbc5 {
+    pub fn new(rumble: bool) -> Self {
+        Self { ram_enabled: false, rom_bank: 1, ram_bank: 0, rumble, motor_on: false, motor_events: Vec::new() }
+    }
+
+    pub fn read(&self, rom: &[u8], ram: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom[addr as usize],
+            0x4000..=0x7FFF => rom[(self.rom_bank as usize * 0x4000 + (addr - 0x4000) as usize) % rom.len()],
+            _ => match self.ram_offset(ram, addr) {
+                Some(offset) => ram[offset],
+                None => 0xFF,
+            },
+        }
+    }
+
+    pub fn write(&mut self, ram: &mut [u8], addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = data == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | data as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((data & 0x01) as u16) << 8,
+            0x4000..=0x5FFF => {
+                if self.rumble {
+                    self.ram_bank = data & 0x07;
+                    self.set_motor(data & 0x08 != 0);
+                } else {
+                    self.ram_bank = data & 0x0F;
+                }
+            },
+            0x6000..=0x7FFF => {},
+            _ => {
+                if let Some(offset) = self.ram_offset(ram, addr) {
+                    ram[offset] = data;
+                }
+            },
+        }
+    }
+
+    pub fn motor_on(&self) -> bool {
+        self.motor_on
+    }
+
+    pub fn take_motor_events(&mut self) -> Vec<MotorEvent> {
+        std::mem::take(&mut self.motor_events)
+    }
+
+    fn set_motor(&mut self, on: bool) {
+        if on != self.motor_on {
+            self.motor_on = on;
+            self.motor_events.push(if on { MotorEvent::Started } else { MotorEvent::Stopped });
+        }
+    }
+
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> Option<usize> {
+        if !self.ram_enabled || ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank as usize * 0x2000 + (addr - 0xA000) as usize) % ram.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::tests::rom_with_header;
+
+    #[test]
+    fn nine_bit_rom_banks() {
+        let mut rom = rom_with_header(0x19, 0x08, 0x00); // 8 MiB
+        rom[0x1FF * 0x4000] = 0xAB;
+        rom[0x100 * 0x4000] = 0xCD;
+        rom[0x4000] = 0x01;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+
+        assert_eq!(cartridge.read(0x4000), 0x01);
+        cartridge.write(0x2000, 0xFF);
+        cartridge.write(0x3000, 0x01);
+        assert_eq!(cartridge.read(0x4000), 0xAB);
+        cartridge.write(0x2000, 0x00);
+        assert_eq!(cartridge.read(0x4000), 0xCD);
+        // Unlike MBC1 and MBC3, bank 0 can be mapped at 0x4000
+        cartridge.write(0x3000, 0x00);
+        assert_eq!(cartridge.read(0x4000), cartridge.read(0x0000));
+    }
+
+    #[test]
+    fn sixteen_ram_banks() {
+        let mut cartridge = Cartridge::new(rom_with_header(0x1B, 0x00, 0x04)).unwrap(); // 128 KiB RAM
+        cartridge.write(0x0000, 0x0A);
+        for bank in 0..16 {
+            cartridge.write(0x4000, bank);
+            cartridge.write(0xA000, bank + 0x20);
+        }
+        cartridge.write(0x4000, 0x0F);
+        assert_eq!(cartridge.read(0xA000), 0x2F);
+        assert_eq!(cartridge.ram()[0x2000 * 9], 0x29);
+
+        // Only 0x0A enables RAM
+        cartridge.write(0x0000, 0x1A);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn rumble_bit_drives_the_motor() {
+        let mut cartridge = Cartridge::new(rom_with_header(0x1E, 0x00, 0x03)).unwrap();
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 0x01);
+        cartridge.write(0xA000, 0x11);
+
+        cartridge.write(0x4000, 0x09);
+        assert_eq!(cartridge.read(0xA000), 0x11); // Bit 3 does not select a RAM bank
+        cartridge.write(0x4000, 0x0A);
+        cartridge.write(0x4000, 0x02);
+        assert_eq!(cartridge.take_motor_events(), vec![MotorEvent::Started, MotorEvent::Stopped]);
+        assert!(cartridge.take_motor_events().is_empty());
+    }
+
+    #[test]
+    fn plain_mbc5_has_no_motor() {
+        let mut cartridge = Cartridge::new(rom_with_header(0x1B, 0x00, 0x04)).unwrap();
+        cartridge.write(0x4000, 0x08);
+        assert!(cartridge.take_motor_events().is_empty());
+    }
+}
@@ -0,0 +1,1191 @@
+// Scanline based PPU, clocked once per M-cycle (4 dots) from the bus.
+//
+// Every line takes 456 dots. Lines 0-143 go through OAM scan (mode 2, 80 dots),
+// drawing (mode 3, 172 dots) and HBlank (mode 0, the rest). Lines 144-153 are VBlank (mode 1).
+//
+// Two renderers are available. The scanline renderer draws a whole line when mode 3 ends.
+// The FIFO renderer runs the background and object fetchers dot by dot, so writes made during
+// mode 3 show up mid-line and mode 3 gets longer with fine scroll, the window and objects.
+//
+//  0xFF40 | LCDC | LCD control
+//  0xFF41 | STAT | Interrupt selects (bits 6-3), LYC=LY (bit 2), mode (bits 1-0)
+//  0xFF42 | SCY  | Background scroll Y
+//  0xFF43 | SCX  | Background scroll X
+//  0xFF44 | LY   | Current line, read only
+//  0xFF45 | LYC  | Line compared against LY
+//  0xFF47 | BGP  | Background palette
+//  0xFF48 | OBP0 | Object palette 0
+//  0xFF49 | OBP1 | Object palette 1
+//  0xFF4A | WY   | Window Y
+//  0xFF4B | WX   | Window X + 7
+//
+// LCDC bits
+//  7 | LCD enable
+//  6 | Window tilemap, 0x9800 or 0x9C00
+//  5 | Window enable
+//  4 | Tile data, 0x8800 (signed indices from 0x9000) or 0x8000 (unsigned)
+//  3 | Background tilemap, 0x9800 or 0x9C00
+//  2 | Object size, 8x8 or 8x16
+//  1 | Object enable
+//  0 | Background and window enable
+//
+// OAM holds 40 objects of 4 bytes: Y + 16, X + 8, tile index and attributes
+//  7 | Background colours 1-3 drawn over the object
+//  6 | Y flip
+//  5 | X flip
+//  4 | Palette, OBP0 or OBP1
+
+use std::collections::VecDeque;
+
+use crate::interrupts::Interrupt;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+const LINES_PER_FRAME: u8 = 154;
+const OBJECTS_PER_LINE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    HBlank, VBlank, OamScan, Drawing
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Renderer {
+    Scanline, Fifo
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FetchStep {
+    Tile, DataLow, DataHigh, Push
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjectPixel {
+    color: u8,
+    obp1: bool,
+    behind_bg: bool,
+}
+
+struct PixelFifo {
+    background: VecDeque<u8>,
+    objects: VecDeque<ObjectPixel>,
+
+    step: FetchStep,
+    // Tile, DataLow and DataHigh take two dots each
+    half_done: bool,
+    fetcher_x: u8,
+    tile: u8,
+    low: u8,
+    high: u8,
+    // The first tile of every line is fetched twice, the first result is thrown away
+    first_fetch: bool,
+    in_window: bool,
+
+    // Pixels dropped before the first one reaches the LCD (SCX fine scroll, or the window at WX < 7)
+    discard: u8,
+    x: u8,
+    // OAM offsets of the objects on this line that still have to be fetched, last one first
+    pending: Vec<usize>,
+    object_dots: u8,
+    // Length of mode 3 so far
+    dots: u16,
+}
+
+impl PixelFifo {
+    fn new() -> Self {
+        Self {
+            background: VecDeque::with_capacity(16),
+            objects: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            half_done: false,
+            fetcher_x: 0,
+            tile: 0,
+            low: 0,
+            high: 0,
+            first_fetch: true,
+            in_window: false,
+            discard: 0,
+            x: 0,
+            pending: Vec::new(),
+            object_dots: 0,
+            dots: 0,
+        }
+    }
+}
+
+impl Mode {
+    fn bits(&self) -> u8 {
+        match self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Drawing => 3,
+        }
+    }
+}
+
+pub struct Ppu {
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+
+    mode: Mode,
+    dot: u16,
+    // STAT interrupts fire on the rising edge of the OR of all selected conditions
+    stat_line: bool,
+    frame_complete: bool,
+    // The window keeps its own line counter, which only advances on lines where it was drawn
+    window_line: u8,
+    // Set once LY has matched WY this frame; the window can only appear after that
+    window_triggered: bool,
+
+    renderer: Renderer,
+    fifo: PixelFifo,
+
+    // Shades 0 (white) to 3 (black), after the palettes have been applied
+    pub framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl Ppu {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self::with_renderer(Renderer::Scanline)
+    }
+
+    pub fn with_renderer(renderer: Renderer) -> Self {
+        Self {
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            dot: 0,
+            stat_line: false,
+            frame_complete: false,
+            window_line: 0,
+            window_triggered: false,
+            renderer,
+            fifo: PixelFifo::new(),
+            framebuffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn tick(&mut self, vram: &[u8], oam: &[u8]) -> u8 {
+        // Advances the PPU by one M-cycle. Returns the interrupt bits (see Interrupt::bit) it requests.
+        if !self.lcd_enabled() {
+            return 0;
+        }
+
+        let mut requested: u8 = 0;
+
+        self.dot += 4;
+        if self.dot >= DOTS_PER_LINE {
+            self.dot -= DOTS_PER_LINE;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+        }
+
+        let mode: Mode = if self.ly >= SCREEN_HEIGHT as u8 {
+            Mode::VBlank
+        } else if self.dot < OAM_SCAN_DOTS {
+            Mode::OamScan
+        } else if self.renderer == Renderer::Fifo {
+            self.step_fifo(vram, oam)
+        } else if self.dot < OAM_SCAN_DOTS + DRAWING_DOTS {
+            Mode::Drawing
+        } else {
+            Mode::HBlank
+        };
+
+        if mode != self.mode {
+            match mode {
+                Mode::HBlank if self.renderer == Renderer::Scanline => self.render_scanline(vram, oam),
+                Mode::VBlank => {
+                    requested |= Interrupt::VBlank.bit();
+                    self.frame_complete = true;
+                    self.window_line = 0;
+                    self.window_triggered = false;
+                },
+                _ => {},
+            }
+            self.mode = mode;
+        }
+
+        if self.update_stat_line() {
+            requested |= Interrupt::Stat.bit();
+        }
+
+        requested
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => 0x80 | self.stat | ((self.ly == self.lyc) as u8) << 2 | self.mode.bits(),
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => panic!("PPU register read out of range: {:#06X}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF40 => {
+                let was_enabled: bool = self.lcd_enabled();
+                self.lcdc = data;
+                if was_enabled && !self.lcd_enabled() {
+                    // Turning the LCD off resets it to the start of line 0
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.mode = Mode::HBlank;
+                    self.stat_line = false;
+                    self.window_line = 0;
+                    self.window_triggered = false;
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.mode = Mode::OamScan;
+                }
+            },
+            0xFF41 => self.stat = data & 0x78, // Only the interrupt selects are writable
+            0xFF42 => self.scy = data,
+            0xFF43 => self.scx = data,
+            0xFF44 => {}, // LY is read only
+            0xFF45 => self.lyc = data,
+            0xFF47 => self.bgp = data,
+            0xFF48 => self.obp0 = data,
+            0xFF49 => self.obp1 = data,
+            0xFF4A => self.wy = data,
+            0xFF4B => self.wx = data,
+            _ => panic!("PPU register write out of range: {:#06X}", addr),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn frame_complete(&mut self) -> bool {
+        // True once per frame, when VBlank starts and the framebuffer holds a full picture
+        let complete: bool = self.frame_complete;
+        self.frame_complete = false;
+        complete
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & (1 << 7) != 0
+    }
+
+    fn update_stat_line(&mut self) -> bool {
+        // Returns true on a rising edge of the STAT interrupt line
+        let line: bool = (self.stat & (1 << 6) != 0 && self.ly == self.lyc)
+            || (self.stat & (1 << 5) != 0 && self.mode == Mode::OamScan)
+            || (self.stat & (1 << 4) != 0 && self.mode == Mode::VBlank)
+            || (self.stat & (1 << 3) != 0 && self.mode == Mode::HBlank);
+
+        let rising: bool = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+
+    fn render_scanline(&mut self, vram: &[u8], oam: &[u8]) {
+        // Colour indices (0-3) of the background and window, before BGP is applied
+        let mut bg_colors: [u8; SCREEN_WIDTH] = [0; SCREEN_WIDTH];
+
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+
+        if self.lcdc & (1 << 0) != 0 {
+            self.render_background(vram, &mut bg_colors);
+            self.render_window(vram, &mut bg_colors);
+        }
+
+        let line: usize = self.ly as usize * SCREEN_WIDTH;
+        for (x, color) in bg_colors.iter().enumerate() {
+            self.framebuffer[line + x] = shade(self.bgp, *color);
+        }
+
+        if self.lcdc & (1 << 1) != 0 {
+            self.render_objects(vram, oam, &bg_colors);
+        }
+    }
+
+    fn render_background(&self, vram: &[u8], bg_colors: &mut [u8; SCREEN_WIDTH]) {
+        let tilemap: u16 = if self.lcdc & (1 << 3) != 0 { 0x9C00 } else { 0x9800 };
+        let y: u8 = self.ly.wrapping_add(self.scy);
+
+        for (screen_x, color) in bg_colors.iter_mut().enumerate() {
+            let x: u8 = (screen_x as u8).wrapping_add(self.scx);
+            *color = self.tilemap_pixel(vram, tilemap, x, y);
+        }
+    }
+
+    fn render_window(&mut self, vram: &[u8], bg_colors: &mut [u8; SCREEN_WIDTH]) {
+        if self.lcdc & (1 << 5) == 0 || !self.window_triggered || self.wx > 166 {
+            return;
+        }
+
+        let tilemap: u16 = if self.lcdc & (1 << 6) != 0 { 0x9C00 } else { 0x9800 };
+        let start: i16 = self.wx as i16 - 7;
+
+        for screen_x in start.max(0)..SCREEN_WIDTH as i16 {
+            let x: u8 = (screen_x - start) as u8;
+            bg_colors[screen_x as usize] = self.tilemap_pixel(vram, tilemap, x, self.window_line);
+        }
+
+        self.window_line += 1;
+    }
+
+    fn object_height(&self) -> u8 {
+        if self.lcdc & (1 << 2) != 0 { 16 } else { 8 }
+    }
+
+    fn scan_oam(&self, oam: &[u8]) -> Vec<usize> {
+        // Returns the OAM offsets of the first 10 objects on this line, highest priority first.
+        // On DMG the object with the smaller X wins, ties go to the one earlier in OAM.
+        let height: u8 = self.object_height();
+        let mut objects: Vec<usize> = (0..oam.len())
+            .step_by(4)
+            .filter(|&offset| {
+                let top: i16 = oam[offset] as i16 - 16;
+                (top..top + height as i16).contains(&(self.ly as i16))
+            })
+            .take(OBJECTS_PER_LINE)
+            .collect();
+
+        // Stable sort, so OAM order is kept between objects at the same X
+        objects.sort_by_key(|&offset| oam[offset + 1]);
+        objects
+    }
+
+    fn object_row(&self, oam: &[u8], offset: usize) -> (u16, u8) {
+        // Address of the tile holding this line of the object, and the row within it
+        let height: u8 = self.object_height();
+        let top: i16 = oam[offset] as i16 - 16;
+        // 8x16 objects ignore bit 0 of the tile index
+        let tile: u8 = if height == 16 { oam[offset + 2] & 0xFE } else { oam[offset + 2] };
+
+        let mut row: u8 = (self.ly as i16 - top) as u8;
+        if oam[offset + 3] & (1 << 6) != 0 {
+            row = height - 1 - row;
+        }
+        (0x8000 + (tile as u16 + row as u16 / 8) * 16, row % 8)
+    }
+
+    fn render_objects(&mut self, vram: &[u8], oam: &[u8], bg_colors: &[u8; SCREEN_WIDTH]) {
+        let line: usize = self.ly as usize * SCREEN_WIDTH;
+        // Set once an opaque object pixel has been found, lower priority objects never show through it
+        let mut drawn: [bool; SCREEN_WIDTH] = [false; SCREEN_WIDTH];
+
+        for offset in self.scan_oam(oam) {
+            let left: i16 = oam[offset + 1] as i16 - 8;
+            let attributes: u8 = oam[offset + 3];
+            let palette: u8 = if attributes & (1 << 4) != 0 { self.obp1 } else { self.obp0 };
+            let (tile_addr, row): (u16, u8) = self.object_row(oam, offset);
+
+            for column in 0..8 {
+                let x: i16 = left + column as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&x) || drawn[x as usize] {
+                    continue;
+                }
+
+                let pixel_x: u8 = if attributes & (1 << 5) != 0 { 7 - column } else { column };
+                let color: u8 = tile_pixel(vram, tile_addr, pixel_x, row);
+                if color == 0 {
+                    continue;
+                }
+
+                drawn[x as usize] = true;
+                if attributes & (1 << 7) != 0 && bg_colors[x as usize] != 0 {
+                    continue;
+                }
+                self.framebuffer[line + x as usize] = shade(palette, color);
+            }
+        }
+    }
+
+    fn tilemap_pixel(&self, vram: &[u8], tilemap: u16, x: u8, y: u8) -> u8 {
+        // Colour index of pixel (x, y) within the 256x256 picture described by a 32x32 tilemap
+        let tile: u8 = vram[(tilemap_address(tilemap, x, y) - 0x8000) as usize];
+        tile_pixel(vram, self.tile_data_address(tile), x % 8, y % 8)
+    }
+
+    fn tile_data_address(&self, tile: u8) -> u16 {
+        if self.lcdc & (1 << 4) != 0 {
+            0x8000 + tile as u16 * 16
+        } else {
+            0x9000u16.wrapping_add((tile as i8 as i16 * 16) as u16)
+        }
+    }
+
+    // Pixel FIFO
+
+    fn step_fifo(&mut self, vram: &[u8], oam: &[u8]) -> Mode {
+        // Runs this M-cycle's 4 dots through the pipeline and returns the mode it leaves the PPU in
+        match self.mode {
+            Mode::HBlank => Mode::HBlank,
+            Mode::Drawing => {
+                for _ in 0..4 {
+                    if self.fifo_dot(vram, oam) {
+                        if self.fifo.in_window {
+                            self.window_line += 1;
+                        }
+                        return Mode::HBlank;
+                    }
+                }
+                Mode::Drawing
+            },
+            _ => {
+                self.start_fifo_line(oam);
+                Mode::Drawing
+            },
+        }
+    }
+
+    fn start_fifo_line(&mut self, oam: &[u8]) {
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+
+        self.fifo = PixelFifo::new();
+        self.fifo.discard = self.scx & 7;
+        self.fifo.pending = self.scan_oam(oam);
+        self.fifo.pending.reverse();
+    }
+
+    fn fifo_dot(&mut self, vram: &[u8], oam: &[u8]) -> bool {
+        // One dot of mode 3. Returns true once the last pixel of the line has reached the LCD.
+        self.fifo.dots += 1;
+
+        if !self.fifo.in_window && self.lcdc & (1 << 5) != 0 && self.window_triggered
+            && self.fifo.x as u16 + 7 >= self.wx as u16 {
+            // The background pixels are dropped and the fetcher restarts on the window tilemap
+            self.fifo.in_window = true;
+            self.fifo.background.clear();
+            self.fifo.step = FetchStep::Tile;
+            self.fifo.half_done = false;
+            self.fifo.fetcher_x = 0;
+            self.fifo.discard = 7u8.saturating_sub(self.wx);
+        }
+
+        if self.lcdc & (1 << 1) != 0 && self.fifo.discard == 0 {
+            if let Some(&offset) = self.fifo.pending.last() {
+                if oam[offset + 1] <= self.fifo.x + 8 {
+                    // Pixel output stalls until the background fetcher has a tile ready, then the object takes 6 dots
+                    let ready = |fifo: &PixelFifo| fifo.step == FetchStep::Push && !fifo.background.is_empty();
+                    if !ready(&self.fifo) {
+                        self.advance_fetcher(vram);
+                    }
+                    if ready(&self.fifo) {
+                        self.fifo.object_dots += 1;
+                        if self.fifo.object_dots == 6 {
+                            self.fifo.object_dots = 0;
+                            self.fifo.pending.pop();
+                            self.fetch_object(vram, oam, offset);
+                        }
+                    }
+                    return false;
+                }
+            }
+        }
+
+        self.advance_fetcher(vram);
+
+        let color: u8 = match self.fifo.background.pop_front() {
+            Some(color) => color,
+            None => return false,
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return false;
+        }
+
+        let bg_color: u8 = if self.lcdc & (1 << 0) != 0 { color } else { 0 };
+        let pixel_shade: u8 = match self.fifo.objects.pop_front() {
+            Some(pixel) if pixel.color != 0 && self.lcdc & (1 << 1) != 0 && !(pixel.behind_bg && bg_color != 0) => {
+                shade(if pixel.obp1 { self.obp1 } else { self.obp0 }, pixel.color)
+            },
+            _ => shade(self.bgp, bg_color),
+        };
+
+        self.framebuffer[self.ly as usize * SCREEN_WIDTH + self.fifo.x as usize] = pixel_shade;
+        self.fifo.x += 1;
+        self.fifo.x == SCREEN_WIDTH as u8
+    }
+
+    fn advance_fetcher(&mut self, vram: &[u8]) {
+        if self.fifo.step == FetchStep::Push {
+            // Waits until the FIFO has room for a whole tile
+            if self.fifo.background.is_empty() {
+                for bit in (0..8).rev() {
+                    let color: u8 = ((self.fifo.high >> bit) & 1) << 1 | ((self.fifo.low >> bit) & 1);
+                    self.fifo.background.push_back(color);
+                }
+                self.fifo.fetcher_x += 1;
+                self.fifo.step = FetchStep::Tile;
+            }
+            return;
+        }
+
+        if !self.fifo.half_done {
+            self.fifo.half_done = true;
+            return;
+        }
+        self.fifo.half_done = false;
+
+        let (tilemap, x, y): (u16, u8, u8) = self.fetcher_position();
+        let row: usize = (self.tile_data_address(self.fifo.tile) - 0x8000) as usize + (y % 8) as usize * 2;
+        match self.fifo.step {
+            FetchStep::Tile => {
+                self.fifo.tile = vram[(tilemap_address(tilemap, x, y) - 0x8000) as usize];
+                self.fifo.step = FetchStep::DataLow;
+            },
+            FetchStep::DataLow => {
+                self.fifo.low = vram[row];
+                self.fifo.step = FetchStep::DataHigh;
+            },
+            FetchStep::DataHigh => {
+                self.fifo.high = vram[row + 1];
+                self.fifo.step = if self.fifo.first_fetch { FetchStep::Tile } else { FetchStep::Push };
+                self.fifo.first_fetch = false;
+            },
+            FetchStep::Push => {},
+        }
+    }
+
+    fn fetcher_position(&self) -> (u16, u8, u8) {
+        // Tilemap and the pixel within its 256x256 picture that the fetcher is on.
+        // Registers are read as the fetch happens, so mid-line writes apply from the next tile.
+        let tile_x: u8 = self.fifo.fetcher_x.wrapping_mul(8);
+        if self.fifo.in_window {
+            let tilemap: u16 = if self.lcdc & (1 << 6) != 0 { 0x9C00 } else { 0x9800 };
+            (tilemap, tile_x, self.window_line)
+        } else {
+            let tilemap: u16 = if self.lcdc & (1 << 3) != 0 { 0x9C00 } else { 0x9800 };
+            (tilemap, (self.scx & !7).wrapping_add(tile_x), self.ly.wrapping_add(self.scy))
+        }
+    }
+
+    fn fetch_object(&mut self, vram: &[u8], oam: &[u8], offset: usize) {
+        // Mixes one line of the object into the object FIFO. Pixels already there keep priority
+        // unless they are transparent, which gives the DMG ordering since objects are fetched by X.
+        let attributes: u8 = oam[offset + 3];
+        let (tile_addr, row): (u16, u8) = self.object_row(oam, offset);
+        // Columns already left of the current pixel (objects with X < 8) are skipped
+        let skip: u8 = self.fifo.x + 8 - oam[offset + 1];
+
+        for column in skip..8 {
+            let pixel_x: u8 = if attributes & (1 << 5) != 0 { 7 - column } else { column };
+            let pixel: ObjectPixel = ObjectPixel {
+                color: tile_pixel(vram, tile_addr, pixel_x, row),
+                obp1: attributes & (1 << 4) != 0,
+                behind_bg: attributes & (1 << 7) != 0,
+            };
+
+            match self.fifo.objects.get_mut((column - skip) as usize) {
+                Some(existing) => if existing.color == 0 {
+                    *existing = pixel;
+                },
+                None => self.fifo.objects.push_back(pixel),
+            }
+        }
+    }
+}
+
+fn tilemap_address(tilemap: u16, x: u8, y: u8) -> u16 {
+    // Tilemap entry covering pixel (x, y)
+    tilemap + (y as u16 / 8) * 32 + (x as u16 / 8)
+}
+
+fn tile_pixel(vram: &[u8], tile_addr: u16, x: u8, y: u8) -> u8 {
+    // Tiles are 8x8, two bytes per row: the low bits of each pixel in the first, the high bits in the second
+    let row: usize = (tile_addr - 0x8000) as usize + y as usize * 2;
+    let bit: u8 = 7 - x;
+
+    let low: u8 = (vram[row] >> bit) & 1;
+    let high: u8 = (vram[row + 1] >> bit) & 1;
+    high << 1 | low
+}
+
+fn shade(palette: u8, color: u8) -> u8 {
+    // Palettes hold one 2-bit shade per colour index
+    (palette >> (color * 2)) & 0b11
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::post_boot_cpu;
+
+    const M_CYCLES_PER_LINE: usize = 114;
+
+    fn enabled_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF40, 0x80);
+        ppu
+    }
+
+    fn ticks(ppu: &mut Ppu, count: usize) -> u8 {
+        let vram = [0; 0x2000];
+        let oam = [0; 0xA0];
+        (0..count).fold(0, |requested, _| requested | ppu.tick(&vram, &oam))
+    }
+
+    #[test]
+    fn modes_follow_the_line_timing() {
+        let mut ppu = enabled_ppu();
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        ticks(&mut ppu, 20);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+        ticks(&mut ppu, 43);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        ticks(&mut ppu, 51);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        assert_eq!(ppu.read(0xFF44), 1);
+        assert_eq!(ppu.read(0xFF41) & 0b11, 2);
+    }
+
+    #[test]
+    fn vblank_starts_at_line_144_and_requests_interrupt() {
+        let mut ppu = enabled_ppu();
+        let requested = ticks(&mut ppu, 144 * M_CYCLES_PER_LINE - 1);
+        assert_eq!(requested & Interrupt::VBlank.bit(), 0);
+        let requested = ticks(&mut ppu, 1);
+        assert_eq!(requested & Interrupt::VBlank.bit(), Interrupt::VBlank.bit());
+        assert_eq!(ppu.read(0xFF44), 144);
+        assert_eq!(ppu.mode(), Mode::VBlank);
+        assert!(ppu.frame_complete());
+        assert!(!ppu.frame_complete());
+    }
+
+    #[test]
+    fn ly_wraps_after_154_lines() {
+        let mut ppu = enabled_ppu();
+        ticks(&mut ppu, 154 * M_CYCLES_PER_LINE);
+        assert_eq!(ppu.read(0xFF44), 0);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+    }
+
+    #[test]
+    fn lyc_match_sets_flag_and_requests_stat() {
+        let mut ppu = enabled_ppu();
+        ppu.write(0xFF45, 2);
+        ppu.write(0xFF41, 1 << 6);
+        let requested = ticks(&mut ppu, 2 * M_CYCLES_PER_LINE);
+        assert_eq!(requested & Interrupt::Stat.bit(), Interrupt::Stat.bit());
+        assert_eq!(ppu.read(0xFF41) & (1 << 2), 1 << 2);
+    }
+
+    #[test]
+    fn stat_interrupt_only_fires_on_rising_edge() {
+        let mut ppu = enabled_ppu();
+        // HBlank and OAM scan selected, with LYC=LY keeping the line high across the mode change
+        ppu.write(0xFF41, (1 << 6) | (1 << 3));
+        ppu.write(0xFF45, 0);
+        ticks(&mut ppu, 1);
+        let requested = ticks(&mut ppu, 100);
+        assert_eq!(requested & Interrupt::Stat.bit(), 0);
+    }
+
+    #[test]
+    fn lcd_off_resets_ly() {
+        let mut ppu = enabled_ppu();
+        ticks(&mut ppu, 5 * M_CYCLES_PER_LINE);
+        ppu.write(0xFF40, 0x00);
+        assert_eq!(ppu.read(0xFF44), 0);
+        assert_eq!(ticks(&mut ppu, 1000), 0);
+        assert_eq!(ppu.read(0xFF44), 0);
+    }
+
+    // Background and window
+
+    fn solid_tile(vram: &mut [u8], tile_addr: u16, color: u8) {
+        // Fills a tile with one colour index
+        let low: u8 = if color & 1 != 0 { 0xFF } else { 0x00 };
+        let high: u8 = if color & 2 != 0 { 0xFF } else { 0x00 };
+        for row in 0..8 {
+            vram[(tile_addr - 0x8000) as usize + row * 2] = low;
+            vram[(tile_addr - 0x8000) as usize + row * 2 + 1] = high;
+        }
+    }
+
+    fn render_line(ppu: &mut Ppu, vram: &[u8], ly: u8) -> Vec<u8> {
+        render_line_with_oam(ppu, vram, &[0; 0xA0], ly)
+    }
+
+    fn render_line_with_oam(ppu: &mut Ppu, vram: &[u8], oam: &[u8], ly: u8) -> Vec<u8> {
+        ppu.ly = ly;
+        ppu.render_scanline(vram, oam);
+        let line = ly as usize * SCREEN_WIDTH;
+        ppu.framebuffer[line..line + SCREEN_WIDTH].to_vec()
+    }
+
+    #[test]
+    fn background_uses_unsigned_tile_data_and_scx() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 3); // Tile 1
+        vram[0x1801] = 1; // 0x9801: second tile of the first row
+
+        let mut ppu = enabled_ppu();
+        ppu.write(0xFF40, 0x91); // LCD on, 0x8000 tile data, background on
+        ppu.write(0xFF47, 0b11_10_01_00);
+
+        let line = render_line(&mut ppu, &vram, 0);
+        assert_eq!(&line[0..8], &[0; 8]);
+        assert_eq!(&line[8..16], &[3; 8]);
+
+        ppu.write(0xFF43, 4);
+        let line = render_line(&mut ppu, &vram, 0);
+        assert_eq!(&line[0..4], &[0; 4]);
+        assert_eq!(&line[4..12], &[3; 8]);
+    }
+
+    #[test]
+    fn background_uses_signed_tile_data() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x9000, 1); // Tile 0 in 0x8800 mode
+        solid_tile(&mut vram, 0x8800, 2); // Tile 0x80 in 0x8800 mode
+        vram[0x1801] = 0x80;
+
+        let mut ppu = enabled_ppu();
+        ppu.write(0xFF40, 0x81);
+        ppu.write(0xFF47, 0b11_10_01_00);
+
+        let line = render_line(&mut ppu, &vram, 0);
+        assert_eq!(&line[0..8], &[1; 8]);
+        assert_eq!(&line[8..16], &[2; 8]);
+    }
+
+    #[test]
+    fn background_scy_wraps_around_the_tilemap() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 3);
+        vram[0x1800 + 31 * 32] = 1; // Last row of the 0x9800 tilemap
+
+        let mut ppu = enabled_ppu();
+        ppu.write(0xFF40, 0x91);
+        ppu.write(0xFF47, 0b11_10_01_00);
+        ppu.write(0xFF42, 248);
+
+        let line = render_line(&mut ppu, &vram, 0);
+        assert_eq!(&line[0..8], &[3; 8]);
+        let line = render_line(&mut ppu, &vram, 8);
+        assert_eq!(&line[0..8], &[0; 8]);
+    }
+
+    #[test]
+    fn window_covers_background_from_wx_and_wy() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 2);
+        vram[0x1C00] = 1; // 0x9C00, first window tile
+
+        let mut ppu = enabled_ppu();
+        ppu.write(0xFF40, 0xF1); // Window on with the 0x9C00 tilemap
+        ppu.write(0xFF47, 0b11_10_01_00);
+        ppu.write(0xFF4A, 10);
+        ppu.write(0xFF4B, 7 + 80);
+
+        let line = render_line(&mut ppu, &vram, 9);
+        assert_eq!(line[80], 0);
+        let line = render_line(&mut ppu, &vram, 10);
+        assert_eq!(&line[79..81], &[0, 2]);
+        assert_eq!(&line[80..88], &[2; 8]);
+        assert_eq!(line[88], 0);
+    }
+
+    #[test]
+    fn window_line_counter_only_advances_when_drawn() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 3);
+        vram[0x1C00 + 32] = 1; // 0x9C20, second window tile row
+
+        let mut ppu = enabled_ppu();
+        ppu.write(0xFF40, 0xF1);
+        ppu.write(0xFF47, 0b11_10_01_00);
+        ppu.write(0xFF4A, 0);
+        ppu.write(0xFF4B, 7);
+
+        for ly in 0..4 {
+            render_line(&mut ppu, &vram, ly);
+        }
+        // Hide the window for a few lines, then bring it back
+        ppu.write(0xFF40, 0xD1);
+        for ly in 4..20 {
+            render_line(&mut ppu, &vram, ly);
+        }
+        ppu.write(0xFF40, 0xF1);
+        for ly in 20..24 {
+            let line = render_line(&mut ppu, &vram, ly);
+            assert_eq!(line[0], 0, "window line {} drawn early", ly);
+        }
+        // The window resumes at its 9th line (tile row 1) even though LY is 24
+        let line = render_line(&mut ppu, &vram, 24);
+        assert_eq!(line[0], 3);
+    }
+
+    #[test]
+    fn background_disabled_draws_colour_zero() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8000, 3);
+
+        let mut ppu = enabled_ppu();
+        ppu.write(0xFF40, 0x90);
+        ppu.write(0xFF47, 0b11_10_01_10);
+
+        let line = render_line(&mut ppu, &vram, 0);
+        assert_eq!(line, vec![2; SCREEN_WIDTH]);
+    }
+
+    // Objects
+
+    fn object(oam: &mut [u8], index: usize, y: u8, x: u8, tile: u8, attributes: u8) {
+        oam[index * 4..index * 4 + 4].copy_from_slice(&[y, x, tile, attributes]);
+    }
+
+    fn object_ppu() -> Ppu {
+        let mut ppu = enabled_ppu();
+        ppu.write(0xFF40, 0x93); // LCD, 0x8000 tile data, objects and background on
+        ppu.write(0xFF47, 0b11_10_01_00);
+        ppu.write(0xFF48, 0b11_10_01_00);
+        ppu.write(0xFF49, 0b00_01_10_11);
+        ppu
+    }
+
+    #[test]
+    fn objects_are_drawn_with_their_palette() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 1);
+        let mut oam = [0; 0xA0];
+        object(&mut oam, 0, 16, 8, 1, 0);
+        object(&mut oam, 1, 16, 20, 1, 1 << 4);
+
+        let mut ppu = object_ppu();
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 0);
+        assert_eq!(&line[0..8], &[1; 8]);
+        assert_eq!(&line[12..20], &[2; 8]);
+        assert_eq!(line[20], 0);
+
+        // Objects disabled
+        ppu.write(0xFF40, 0x91);
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 0);
+        assert_eq!(&line[0..8], &[0; 8]);
+    }
+
+    #[test]
+    fn objects_can_be_flipped() {
+        let mut vram = [0; 0x2000];
+        vram[0x10] = 0x80; // Tile 1, row 0: leftmost pixel colour 1
+        let mut oam = [0; 0xA0];
+        object(&mut oam, 0, 16, 8, 1, 1 << 5);
+        object(&mut oam, 1, 16, 30, 1, 1 << 6);
+
+        let mut ppu = object_ppu();
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 0);
+        assert_eq!(&line[0..8], &[0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(line[22], 0);
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 7);
+        assert_eq!(line[22], 1);
+    }
+
+    #[test]
+    fn tall_objects_span_two_tiles() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8020, 1); // Tile 2
+        solid_tile(&mut vram, 0x8030, 2); // Tile 3
+        let mut oam = [0; 0xA0];
+        object(&mut oam, 0, 16, 8, 3, 0); // Bit 0 of the tile is ignored
+        object(&mut oam, 1, 16, 40, 2, 1 << 6);
+
+        let mut ppu = object_ppu();
+        ppu.write(0xFF40, 0x97);
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[0], 1);
+        assert_eq!(line[32], 2);
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 15);
+        assert_eq!(line[0], 2);
+        assert_eq!(line[32], 1);
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 16);
+        assert_eq!(line[0], 0);
+    }
+
+    #[test]
+    fn only_ten_objects_per_line() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 3);
+        let mut oam = [0; 0xA0];
+        // An off screen object still takes one of the ten slots
+        object(&mut oam, 0, 16, 0, 1, 0);
+        for index in 1..12 {
+            object(&mut oam, index, 16, 8 + index as u8 * 8, 1, 0);
+        }
+
+        let mut ppu = object_ppu();
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[8 * 9], 3);
+        assert_eq!(line[8 * 10], 0);
+        assert_eq!(line[8 * 11], 0);
+    }
+
+    #[test]
+    fn smaller_x_wins_then_oam_order() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 1);
+        solid_tile(&mut vram, 0x8020, 2);
+        vram[0x30] = 0x0F; // Tile 3, row 0: right half colour 1
+        let mut oam = [0; 0xA0];
+        object(&mut oam, 0, 16, 12, 1, 0);
+        object(&mut oam, 1, 16, 8, 2, 0);
+        object(&mut oam, 2, 16, 50, 1, 0);
+        object(&mut oam, 3, 16, 50, 2, 0);
+        // Transparent pixels of the winning object let lower priority objects through
+        object(&mut oam, 4, 16, 80, 3, 0);
+        object(&mut oam, 5, 16, 81, 2, 0);
+
+        let mut ppu = object_ppu();
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 0);
+        assert_eq!(&line[0..8], &[2; 8]);
+        assert_eq!(&line[8..12], &[1; 4]);
+        assert_eq!(&line[42..50], &[1; 8]);
+        assert_eq!(&line[72..77], &[0, 2, 2, 2, 1]);
+        assert_eq!(line[80], 2);
+    }
+
+    #[test]
+    fn background_priority_only_hides_objects_over_colours_1_to_3() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 1);
+        solid_tile(&mut vram, 0x8020, 2);
+        vram[0x1800] = 2; // Background colour 2 over the first tile, colour 0 elsewhere
+        let mut oam = [0; 0xA0];
+        object(&mut oam, 0, 16, 12, 1, 1 << 7);
+        // A lower priority object under a hidden pixel stays hidden
+        object(&mut oam, 1, 16, 13, 2, 0);
+
+        let mut ppu = object_ppu();
+        ppu.write(0xFF48, 0b11_11_11_00);
+        let line = render_line_with_oam(&mut ppu, &vram, &oam, 0);
+        assert_eq!(&line[4..8], &[2; 4]);
+        assert_eq!(&line[8..12], &[3; 4]);
+        assert_eq!(line[12], 3);
+    }
+
+    // Pixel FIFO
+
+    fn fifo_ppu() -> Ppu {
+        let mut ppu = Ppu::with_renderer(Renderer::Fifo);
+        ppu.write(0xFF40, 0x93);
+        ppu.write(0xFF47, 0b11_10_01_00);
+        ppu.write(0xFF48, 0b11_10_01_00);
+        ppu.write(0xFF49, 0b00_01_10_11);
+        ppu
+    }
+
+    fn ticks_with(ppu: &mut Ppu, vram: &[u8], oam: &[u8], count: usize) {
+        for _ in 0..count {
+            ppu.tick(vram, oam);
+        }
+    }
+
+    fn drawing_dots(ppu: &mut Ppu, vram: &[u8], oam: &[u8]) -> u16 {
+        // Length of mode 3 on the first line
+        ticks_with(ppu, vram, oam, M_CYCLES_PER_LINE);
+        ppu.fifo.dots
+    }
+
+    fn test_scene() -> ([u8; 0x2000], [u8; 0xA0]) {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 1);
+        solid_tile(&mut vram, 0x8020, 2);
+        vram[0x30..0x40].copy_from_slice(&[0x3C, 0x0F, 0x42, 0xF0, 0x81, 0x33, 0x18, 0xCC, 0x24, 0x55, 0xFF, 0x00, 0x00, 0xFF, 0xA5, 0x5A]);
+        for (index, entry) in vram[0x1800..0x1C00].iter_mut().enumerate() {
+            *entry = (index % 7 % 4) as u8;
+        }
+        vram[0x1C00..0x1C20].fill(2);
+
+        let mut oam = [0; 0xA0];
+        object(&mut oam, 0, 20, 5, 3, 0);
+        object(&mut oam, 1, 22, 9, 3, (1 << 5) | (1 << 4));
+        object(&mut oam, 2, 30, 60, 3, 1 << 7);
+        object(&mut oam, 3, 30, 64, 3, 1 << 6);
+        object(&mut oam, 4, 40, 160, 3, 0);
+        (vram, oam)
+    }
+
+    #[test]
+    fn fifo_draws_the_same_frame_as_the_scanline_renderer() {
+        let (vram, oam) = test_scene();
+        let mut scanline = object_ppu();
+        let mut fifo = fifo_ppu();
+        for ppu in [&mut scanline, &mut fifo] {
+            ppu.write(0xFF42, 3);
+            ppu.write(0xFF43, 13);
+            ppu.write(0xFF4A, 50);
+            ppu.write(0xFF4B, 7 + 100);
+            ppu.write(0xFF40, 0xB3);
+            ticks_with(ppu, &vram, &oam, 154 * M_CYCLES_PER_LINE);
+        }
+
+        assert!(scanline.framebuffer.iter().any(|&shade| shade != 0));
+        assert!(scanline.framebuffer == fifo.framebuffer);
+    }
+
+    #[test]
+    fn fifo_mode_3_lasts_172_dots_and_matches_scanline_timing() {
+        let vram = [0; 0x2000];
+        let oam = [0; 0xA0];
+        let mut ppu = fifo_ppu();
+        ticks_with(&mut ppu, &vram, &oam, 20);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+        ticks_with(&mut ppu, &vram, &oam, 42);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+        ticks_with(&mut ppu, &vram, &oam, 1);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        assert_eq!(ppu.fifo.dots, 172);
+    }
+
+    #[test]
+    fn fifo_fine_scroll_lengthens_mode_3() {
+        let vram = [0; 0x2000];
+        let oam = [0; 0xA0];
+        let mut ppu = fifo_ppu();
+        ppu.write(0xFF43, 0x0D);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172 + 5);
+    }
+
+    #[test]
+    fn fifo_window_costs_six_dots() {
+        let vram = [0; 0x2000];
+        let oam = [0; 0xA0];
+        let mut ppu = fifo_ppu();
+        ppu.write(0xFF4A, 0);
+        ppu.write(0xFF4B, 7 + 40);
+        ppu.write(0xFF40, 0xB3);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172 + 6);
+        assert_eq!(ppu.window_line, 1);
+    }
+
+    #[test]
+    fn fifo_objects_cost_six_to_twelve_dots() {
+        let vram = [0; 0x2000];
+        let mut oam = [0; 0xA0];
+        // Fetched while the background fetcher is waiting to push: no extra wait
+        object(&mut oam, 0, 16, 8 + 15, 0, 0);
+        let mut ppu = fifo_ppu();
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172 + 6);
+
+        // Fetched on a tile boundary: the background fetcher has to finish the next tile first
+        object(&mut oam, 0, 16, 8 + 16, 0, 0);
+        let mut ppu = fifo_ppu();
+        let dots = drawing_dots(&mut ppu, &vram, &oam);
+        assert!(dots > 172 + 6 && dots <= 172 + 12, "mode 3 took {} dots", dots);
+
+        // Objects are skipped entirely while disabled
+        let mut ppu = fifo_ppu();
+        ppu.write(0xFF40, 0x91);
+        assert_eq!(drawing_dots(&mut ppu, &vram, &oam), 172);
+    }
+
+    #[test]
+    fn fifo_applies_palette_writes_mid_line() {
+        let mut vram = [0; 0x2000];
+        solid_tile(&mut vram, 0x8010, 1);
+        vram[0x1800..0x1820].fill(1);
+        let oam = [0; 0xA0];
+
+        let mut ppu = fifo_ppu();
+        ppu.write(0xFF47, 0b00_00_01_00);
+        // 20 M-cycles of OAM scan, then 12 dots before the first pixel and 4 pixels per M-cycle
+        ticks_with(&mut ppu, &vram, &oam, 20 + 3 + 10);
+        ppu.write(0xFF47, 0b00_00_11_00);
+        ticks_with(&mut ppu, &vram, &oam, 50);
+
+        let line = &ppu.framebuffer[0..SCREEN_WIDTH];
+        let split = line.iter().position(|&shade| shade == 3).unwrap();
+        assert!(split > 0 && split < SCREEN_WIDTH);
+        assert!(line[..split].iter().all(|&shade| shade == 1));
+        assert!(line[split..].iter().all(|&shade| shade == 3));
+    }
+
+    fn read_pgm(path: &str) -> Vec<u8> {
+        // Reads a binary (P5) PGM and returns its pixels as shades, white being 0 and black 3
+        let data = std::fs::read(path).unwrap_or_else(|err| panic!("Could not read {}: {}", path, err));
+        let mut fields = 0;
+        let mut start = 0;
+        while fields < 4 {
+            while data[start].is_ascii_whitespace() {
+                start += 1;
+            }
+            while !data[start].is_ascii_whitespace() {
+                start += 1;
+            }
+            fields += 1;
+        }
+        let header = String::from_utf8_lossy(&data[..start]).into_owned();
+        assert_eq!(header.split_whitespace().collect::<Vec<_>>(), ["P5", "160", "144", "255"], "{} is not a 160x144 PGM", path);
+        data[start + 1..].iter().map(|gray| ((255 - *gray as u16 + 42) / 85) as u8).collect()
+    }
+
+    fn render_frames(rom: Vec<u8>, renderer: Renderer, frames: usize) -> [u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        // Runs a ROM from the post-boot state and returns the last of the given number of frames
+        let mut cpu = post_boot_cpu(rom, renderer);
+        let mut completed = 0;
+        while completed < frames {
+            cpu.step();
+            if cpu.bus.ppu.frame_complete() {
+                completed += 1;
+            }
+        }
+        cpu.bus.ppu.framebuffer
+    }
+
+    #[test]
+    #[ignore]
+    fn dmg_acid2() {
+        // Renders dmg-acid2 with both renderers and compares each picture against the reference. Neither is part
+        // of the repository, and the reference has to be converted from PNG to a binary PGM first, e.g.
+        //   magick reference-dmg.png dmg-acid2.pgm
+        //   DMG_ACID2_ROM=dmg-acid2.gb DMG_ACID2_REFERENCE=dmg-acid2.pgm cargo test dmg_acid2 -- --ignored
+        let rom_path = std::env::var("DMG_ACID2_ROM").expect("DMG_ACID2_ROM is not set");
+        let reference_path = std::env::var("DMG_ACID2_REFERENCE").expect("DMG_ACID2_REFERENCE is not set");
+        let reference = read_pgm(&reference_path);
+        assert_eq!(reference.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        let rom = std::fs::read(&rom_path).unwrap_or_else(|err| panic!("Could not read {}: {}", rom_path, err));
+
+        let mut failures: Vec<String> = Vec::new();
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let framebuffer = render_frames(rom.clone(), renderer, 10);
+            let wrong: Vec<(usize, usize)> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+                .filter(|i| framebuffer[*i] != reference[*i])
+                .map(|i| (i % SCREEN_WIDTH, i / SCREEN_WIDTH))
+                .collect();
+            if !wrong.is_empty() {
+                failures.push(format!("{:?}: {} pixels differ, first at {:?}", renderer, wrong.len(), wrong[0]));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("; "));
+    }
+}
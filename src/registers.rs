@@ -1,7 +1,18 @@
 #[derive(Debug)]
 pub enum Register {
     A, B, C, D, E, H, L, F,
-    BC, DE, HL,
+    AF, BC, DE, HL, SP, PC,
+}
+#[derive(Debug)]
+pub enum Operand8 {
+    Register(Register),
+    HL, // The byte in memory pointed to by HL
+}
+impl Operand8 {
+    pub fn is_memory(&self) -> bool {
+        // Memory operands cost an extra M-cycle per access
+        matches!(self, Operand8::HL)
+    }
 }
 pub enum Flag {
     Z, N, H, C
@@ -16,11 +27,13 @@ pub struct Registers {
     e: u8,
     h: u8,
     l: u8,
+    sp: u16,
+    pc: u16,
 }
 
 impl Registers {
     pub fn new() -> Self {
-        Self { a: 0, f: 0, b: 0, c: 0, d: 0, e: 0, h: 0, l: 0}
+        Self { a: 0, f: 0, b: 0, c: 0, d: 0, e: 0, h: 0, l: 0, sp: 0, pc: 0}
     }
 
     pub fn get_8(&self, reg: &Register) -> u8 {
@@ -39,7 +52,7 @@ impl Registers {
     pub fn set_8(&mut self, reg: &Register, value: u8) {
         match reg {
             Register::A => self.a = value,
-            Register::F => self.f = value,
+            Register::F => self.f = value & 0xF0, // The lower nibble of F always reads 0
             Register::B => self.b = value,
             Register::C => self.c = value,
             Register::D => self.d = value,
@@ -53,14 +66,21 @@ impl Registers {
 
     pub fn get_16(&self, reg: &Register) -> u16{
         match reg {
+            Register::AF => (self.a as u16) << 8 | self.f as u16,
             Register::BC => (self.b as u16) << 8 | self.c as u16,
             Register::DE => (self.d as u16) << 8 | self.e as u16,
             Register::HL => (self.h as u16) << 8 | self.l as u16,
+            Register::SP => self.sp,
+            Register::PC => self.pc,
             _ => panic!("16-bit register can't be read")
         }
     }
     pub fn set_16(&mut self, reg: &Register, value: u16){
         match reg {
+            Register::AF =>{
+                self.a = (value >> 8) as u8;
+                self.f = (value & 0xF0) as u8;
+            },
             Register::BC =>{
                 self.b = (value >> 8) as u8;
                 self.c = (value & 0xFF) as u8;
@@ -73,6 +93,8 @@ impl Registers {
                 self.h = (value >> 8) as u8;
                 self.l = (value & 0xFF) as u8;
             },
+            Register::SP => self.sp = value,
+            Register::PC => self.pc = value,
             _ => panic!("16-bit register can't be read")
         }
     }
@@ -102,30 +124,34 @@ impl Registers {
     }
 
     
-    pub fn decode_register_8(&self, index: u8) -> Register{
-        match index {
-            0b000 => Register::B,
-            0b001 => Register::C,
-            0b010 => Register::D,
-            0b011 => Register::E,
-            0b100 => Register::H,
-            0b101 => Register::L,
-            0b110 => Register::HL,
-            0b111 => Register::A, 
-            _ => todo!() 
-
+    pub fn decode_register_8(&self, index: u8) -> Operand8{
+        match index & 0b111 {
+            0b000 => Operand8::Register(Register::B),
+            0b001 => Operand8::Register(Register::C),
+            0b010 => Operand8::Register(Register::D),
+            0b011 => Operand8::Register(Register::E),
+            0b100 => Operand8::Register(Register::H),
+            0b101 => Operand8::Register(Register::L),
+            0b110 => Operand8::HL,
+            _ => Operand8::Register(Register::A),
         }
     }
     pub fn decode_register_16(&self, index: u8) -> Register{
-        match index {
-            0b000 => Register::BC,
-            0b001 => Register::BC,
-            0b010 => Register::DE,
-            0b011 => Register::DE,
-            0b100 => Register::HL,
-            0b101 => Register::HL,
-            _ => todo!() 
-
+        // rp table, used by LD r16, INC/DEC r16 and ADD HL, r16
+        match index & 0b11 {
+            0b00 => Register::BC,
+            0b01 => Register::DE,
+            0b10 => Register::HL,
+            _ => Register::SP,
+        }
+    }
+    pub fn decode_register_16_stack(&self, index: u8) -> Register{
+        // rp2 table, used by PUSH and POP
+        match index & 0b11 {
+            0b00 => Register::BC,
+            0b01 => Register::DE,
+            0b10 => Register::HL,
+            _ => Register::AF,
         }
     }
 
@@ -0,0 +1,246 @@
+// Serial port, clocked once per M-cycle from the bus.
+//
+//  0xFF01 | SB | Byte being shifted out, MSB first, while the received bits are shifted in
+//  0xFF02 | SC | Bit 7 transfer requested/running, bit 0 clock (1 = internal)
+//
+// With the internal clock a bit is shifted every 128 M-cycles (8192 Hz), so a byte takes 1024 M-cycles.
+// With nothing connected every received bit is 1. Sent bytes are kept so tests and frontends can read
+// what the game printed, e.g. the results of Blargg's test ROMs.
+//
+// A Link connects the port to another console. The side using the internal clock swaps bytes when its
+// transfer ends; the side using the external clock waits until the other side clocks a byte in.
+
+const CYCLES_PER_BIT: u16 = 128;
+
+pub trait Link {
+    // Clocks a byte out to the other side and returns the byte it shifted back
+    fn exchange(&mut self, byte: u8) -> u8;
+    // Returns a byte clocked in by the other side, if one arrived, and answers it with `reply` (this side's SB)
+    fn poll(&mut self, reply: u8) -> Option<u8>;
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // Byte as it was when the transfer started
+    outgoing: u8,
+    bits_left: u8,
+    counter: u16,
+    output: Vec<u8>,
+    link: Option<Box<dyn Link>>,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self { sb: 0, sc: 0, outgoing: 0, bits_left: 0, counter: 0, output: Vec::new(), link: None }
+    }
+
+    pub fn tick(&mut self) -> bool {
+        // Advances a running internal-clock transfer by one M-cycle. Returns true when the Serial interrupt should be requested.
+        if let Some(link) = &mut self.link {
+            if let Some(byte) = link.poll(self.sb) {
+                return self.clocked_externally(byte);
+            }
+        }
+
+        if self.bits_left == 0 || self.sc & 0x01 == 0 {
+            return false;
+        }
+
+        self.counter += 1;
+        if self.counter < CYCLES_PER_BIT {
+            return false;
+        }
+        self.counter = 0;
+
+        self.sb = (self.sb << 1) | 1;
+        self.bits_left -= 1;
+        if self.bits_left > 0 {
+            return false;
+        }
+
+        if let Some(link) = &mut self.link {
+            self.sb = link.exchange(self.outgoing);
+        }
+        self.sc &= 0x7F;
+        self.output.push(self.outgoing);
+        true
+    }
+
+    fn clocked_externally(&mut self, byte: u8) -> bool {
+        // The other side shifted a whole byte through SB. Only completes a transfer waiting on the external clock,
+        // SB is left alone when none is.
+        if self.sc & 0x81 != 0x80 {
+            return false;
+        }
+
+        self.sb = byte;
+        self.sc &= 0x7F;
+        self.bits_left = 0;
+        self.output.push(self.outgoing);
+        true
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => self.sc | 0x7E,
+            _ => panic!("Serial register read out of range: {:#06X}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF01 => self.sb = data,
+            0xFF02 => {
+                self.sc = data & 0x81;
+                if self.sc & 0x80 != 0 {
+                    self.outgoing = self.sb;
+                    self.bits_left = 8;
+                    self.counter = 0;
+                } else {
+                    self.bits_left = 0;
+                }
+            },
+            _ => panic!("Serial register write out of range: {:#06X}", addr),
+        }
+    }
+
+    pub fn connect(&mut self, link: Box<dyn Link>) {
+        self.link = Some(link);
+    }
+
+    #[cfg(test)]
+    pub fn output(&self) -> &[u8] {
+        // Every byte sent so far
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(serial: &mut Serial, byte: u8) {
+        serial.write(0xFF01, byte);
+        serial.write(0xFF02, 0x81);
+    }
+
+    #[test]
+    fn internal_clock_transfer_takes_1024_cycles() {
+        let mut serial = Serial::new();
+        transfer(&mut serial, 0x42);
+        assert_eq!(serial.read(0xFF02), 0xFF);
+
+        for _ in 0..1023 {
+            assert!(!serial.tick());
+        }
+        assert!(serial.tick());
+        assert_eq!(serial.read(0xFF02), 0x7F);
+        // Nothing connected, so only 1s came in
+        assert_eq!(serial.read(0xFF01), 0xFF);
+        assert!(!serial.tick());
+    }
+
+    #[test]
+    fn bits_are_shifted_out_msb_first() {
+        let mut serial = Serial::new();
+        transfer(&mut serial, 0x05);
+        for _ in 0..3 * CYCLES_PER_BIT {
+            serial.tick();
+        }
+        assert_eq!(serial.read(0xFF01), 0x2F);
+    }
+
+    #[test]
+    fn external_clock_transfer_waits() {
+        let mut serial = Serial::new();
+        serial.write(0xFF01, 0x42);
+        serial.write(0xFF02, 0x80);
+        for _ in 0..5000 {
+            assert!(!serial.tick());
+        }
+        assert_eq!(serial.read(0xFF02), 0xFE);
+        assert!(serial.output().is_empty());
+    }
+
+    #[test]
+    fn sent_bytes_are_captured() {
+        let mut serial = Serial::new();
+        for byte in b"ok" {
+            transfer(&mut serial, *byte);
+            for _ in 0..1024 {
+                serial.tick();
+            }
+        }
+        assert_eq!(serial.output(), b"ok");
+        assert_eq!(serial.take_output(), b"ok".to_vec());
+        assert!(serial.output().is_empty());
+    }
+
+    // Stands in for the other console: answers every byte with a fixed one and can clock a byte in
+    struct Peer {
+        reply: u8,
+        clock: Option<u8>,
+        received: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+    }
+
+    impl Link for Peer {
+        fn exchange(&mut self, byte: u8) -> u8 {
+            self.received.borrow_mut().push(byte);
+            self.reply
+        }
+        fn poll(&mut self, reply: u8) -> Option<u8> {
+            let byte = self.clock.take();
+            if byte.is_some() {
+                self.received.borrow_mut().push(reply);
+            }
+            byte
+        }
+    }
+
+    #[test]
+    fn internal_clock_exchanges_with_the_link() {
+        let received = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Peer { reply: 0x99, clock: None, received: received.clone() }));
+
+        transfer(&mut serial, 0x42);
+        let interrupts = (0..1024).filter(|_| serial.tick()).count();
+        assert_eq!(interrupts, 1);
+        assert_eq!(serial.read(0xFF01), 0x99);
+        assert_eq!(*received.borrow(), vec![0x42]);
+    }
+
+    #[test]
+    fn external_clock_completes_when_the_other_side_clocks() {
+        let received = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut serial = Serial::new();
+        serial.write(0xFF01, 0x24);
+        serial.write(0xFF02, 0x80);
+        serial.connect(Box::new(Peer { reply: 0, clock: Some(0x77), received: received.clone() }));
+
+        assert!(serial.tick());
+        assert_eq!(serial.read(0xFF01), 0x77);
+        assert_eq!(serial.read(0xFF02), 0x7E);
+        assert_eq!(*received.borrow(), vec![0x24]);
+        assert_eq!(serial.output(), &[0x24]);
+    }
+
+    #[test]
+    fn clock_without_a_pending_transfer_leaves_sb_alone() {
+        let received = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut serial = Serial::new();
+        serial.write(0xFF01, 0x24);
+        serial.write(0xFF02, 0x00);
+        serial.connect(Box::new(Peer { reply: 0, clock: Some(0x77), received: received.clone() }));
+
+        assert!(!serial.tick());
+        assert_eq!(serial.read(0xFF01), 0x24);
+        assert!(serial.output().is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+// DIV/TIMA/TMA/TAC timer, clocked once per M-cycle from the bus.
+//
+//  0xFF04 | DIV  | Upper 8 bits of the 16-bit internal divider, any write resets it
+//  0xFF05 | TIMA | Counter, incremented on the falling edge of the divider bit selected by TAC
+//  0xFF06 | TMA  | Value TIMA is reloaded with one M-cycle after it overflows
+//  0xFF07 | TAC  | Bit 2 enable, bits 1-0 select the divider bit (9, 3, 5 or 7)
+
+pub struct Timer {
+    divider: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed during the last M-cycle and reads 0 until it is reloaded on the next one
+    overflow_pending: bool,
+    // TIMA was reloaded from TMA during the last M-cycle; writes to TIMA are ignored until the next one
+    reloading: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self { divider: 0, tima: 0, tma: 0, tac: 0, overflow_pending: false, reloading: false }
+    }
+
+    pub fn tick(&mut self) -> bool {
+        // Advances the timer by one M-cycle (4 clock ticks). Returns true when the Timer interrupt should be requested.
+        let mut interrupt: bool = false;
+
+        self.reloading = false;
+        if self.overflow_pending {
+            self.overflow_pending = false;
+            self.tima = self.tma;
+            self.reloading = true;
+            interrupt = true;
+        }
+
+        let before: bool = self.timer_bit();
+        self.divider = self.divider.wrapping_add(4);
+        if before && !self.timer_bit() {
+            self.increment_tima();
+        }
+
+        interrupt
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.divider >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => panic!("Timer register read out of range: {:#06X}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF04 => {
+                // Resetting the divider can itself produce a falling edge on the selected bit
+                let before: bool = self.timer_bit();
+                self.divider = 0;
+                if before {
+                    self.increment_tima();
+                }
+            },
+            0xFF05 => {
+                // A write in the M-cycle after an overflow cancels the reload and the interrupt,
+                // a write in the M-cycle of the reload is overridden by TMA
+                if !self.reloading {
+                    self.tima = data;
+                    self.overflow_pending = false;
+                }
+            },
+            0xFF06 => {
+                self.tma = data;
+                if self.reloading {
+                    self.tima = data;
+                }
+            },
+            0xFF07 => {
+                // Disabling the timer or switching bits can also produce a falling edge
+                let before: bool = self.timer_bit();
+                self.tac = data & 0x07;
+                if before && !self.timer_bit() {
+                    self.increment_tima();
+                }
+            },
+            _ => panic!("Timer register write out of range: {:#06X}", addr),
+        }
+    }
+
+    fn timer_bit(&self) -> bool {
+        // The selected divider bit ANDed with the enable bit; TIMA ticks when this falls
+        let bit: u16 = match self.tac & 0b11 {
+            0b00 => 9, // 4096 Hz
+            0b01 => 3, // 262144 Hz
+            0b10 => 5, // 65536 Hz
+            _ => 7,    // 16384 Hz
+        };
+        self.tac & 0b100 != 0 && self.divider & (1 << bit) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (result, overflow) = self.tima.overflowing_add(1);
+        self.tima = result;
+        if overflow {
+            self.overflow_pending = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::rom_with_header;
+    use crate::cpu::tests::post_boot_cpu;
+    use crate::ppu::Renderer;
+    use crate::registers::Register;
+
+    fn ticks(timer: &mut Timer, count: usize) -> usize {
+        // Runs count M-cycles and returns how many of them requested an interrupt
+        (0..count).filter(|_| timer.tick()).count()
+    }
+
+    #[test]
+    fn div_increments_every_64_m_cycles() {
+        let mut timer = Timer::new();
+        ticks(&mut timer, 63);
+        assert_eq!(timer.read(0xFF04), 0);
+        ticks(&mut timer, 1);
+        assert_eq!(timer.read(0xFF04), 1);
+    }
+
+    #[test]
+    fn div_write_resets_the_divider() {
+        let mut timer = Timer::new();
+        ticks(&mut timer, 200);
+        timer.write(0xFF04, 0x55);
+        assert_eq!(timer.read(0xFF04), 0);
+        ticks(&mut timer, 64);
+        assert_eq!(timer.read(0xFF04), 1);
+    }
+
+    #[test]
+    fn tima_follows_the_selected_frequency() {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0b101); // Enabled, every 16 clocks
+        ticks(&mut timer, 4 * 10);
+        assert_eq!(timer.read(0xFF05), 10);
+
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0b100); // Enabled, every 1024 clocks
+        ticks(&mut timer, 256);
+        assert_eq!(timer.read(0xFF05), 1);
+    }
+
+    #[test]
+    fn disabled_timer_does_not_count() {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0b001);
+        ticks(&mut timer, 100);
+        assert_eq!(timer.read(0xFF05), 0);
+        assert_eq!(timer.read(0xFF07), 0xF9);
+    }
+
+    #[test]
+    fn overflow_reloads_tma_one_cycle_late_and_requests_interrupt() {
+        let mut timer = Timer::new();
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF07, 0b101);
+
+        assert_eq!(ticks(&mut timer, 4), 0);
+        assert_eq!(timer.read(0xFF05), 0x00);
+        assert!(timer.tick());
+        assert_eq!(timer.read(0xFF05), 0xAB);
+    }
+
+    #[test]
+    fn tima_write_after_overflow_cancels_reload() {
+        let mut timer = Timer::new();
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF07, 0b101);
+
+        ticks(&mut timer, 4);
+        timer.write(0xFF05, 0x12);
+        assert!(!timer.tick());
+        assert_eq!(timer.read(0xFF05), 0x12);
+    }
+
+    #[test]
+    fn tima_write_during_reload_is_ignored() {
+        let mut timer = Timer::new();
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF07, 0b101);
+
+        ticks(&mut timer, 5);
+        timer.write(0xFF05, 0x12);
+        assert_eq!(timer.read(0xFF05), 0xAB);
+        timer.write(0xFF06, 0xCD);
+        assert_eq!(timer.read(0xFF05), 0xCD);
+    }
+
+    #[test]
+    fn div_reset_ticks_tima_when_selected_bit_is_high() {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0b101);
+        ticks(&mut timer, 2); // Divider bit 3 is now set
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 1);
+    }
+
+    #[test]
+    fn disabling_timer_while_selected_bit_is_high_ticks_tima() {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0b101);
+        ticks(&mut timer, 2);
+        timer.write(0xFF07, 0b001);
+        assert_eq!(timer.read(0xFF05), 1);
+    }
+
+    // The ROM tests below need test ROMs that are not part of the repository, so they are ignored and
+    // take their paths from the environment, e.g.
+    //   BLARGG_INSTR_TIMING_ROM=instr_timing.gb MOONEYE_TIMER_DIR=mts/acceptance/timer cargo test -- --ignored
+
+    // About a minute of emulated time
+    const ROM_CYCLE_LIMIT: u64 = 60 * 60 * 17556;
+
+    fn rom_from_env(var: &str) -> Vec<u8> {
+        let path = std::env::var(var).unwrap_or_else(|_| panic!("{} is not set", var));
+        std::fs::read(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path, err))
+    }
+
+    fn run_mooneye(rom: Vec<u8>) -> [u8; 6] {
+        // Runs until the LD B,B breakpoint mooneye's tests finish on and returns B, C, D, E, H and L
+        let mut cpu = post_boot_cpu(rom, Renderer::Scanline);
+        while cpu.get_cycles() < ROM_CYCLE_LIMIT && cpu.bus.read(cpu.get_pc()) != 0x40 {
+            cpu.step();
+        }
+        [Register::B, Register::C, Register::D, Register::E, Register::H, Register::L].map(|register| cpu.register.get_8(&register))
+    }
+
+    const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+    fn tima_rom(expected: u8) -> Vec<u8> {
+        // A ROM in the style of mooneye's timer tests: resets DIV, clears TIMA at 4 M-cycles per increment,
+        // waits 12 NOPs and ends with the pass signature only if TIMA reads back as expected
+        let mut rom = rom_with_header(0x00, 0x00, 0x00);
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // NOP ; JP 0x0150
+        let mut program: Vec<u8> = vec![0xF3, 0x3E, 0x05, 0xE0, 0x07, 0xAF, 0xE0, 0x04, 0xE0, 0x05]; // DI ; TAC = 0b101 ; DIV = TIMA = 0
+        program.extend([0x00; 12]);
+        program.extend([0xF0, 0x05, 0xFE, expected, 0x20, 0x0D]); // LDH A, (TIMA) ; CP expected ; JR NZ, fail
+        program.extend([0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40]); // Signature ; LD B, B
+        program.extend([0x06, 0x42, 0x40]); // fail: LD B, 0x42 ; LD B, B
+        rom[0x150..0x150 + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    #[test]
+    fn mooneye_harness_reads_the_signature() {
+        // TIMA is cleared 3 M-cycles after the DIV reset and read 18 M-cycles after it, so it increments at 4, 8, 12 and 16
+        assert_eq!(run_mooneye(tima_rom(4)), MOONEYE_PASS);
+        assert_ne!(run_mooneye(tima_rom(3)), MOONEYE_PASS);
+    }
+
+    #[test]
+    #[ignore]
+    fn blargg_instr_timing() {
+        // instr_timing measures every instruction against the timer and prints the result over serial
+        let mut cpu = post_boot_cpu(rom_from_env("BLARGG_INSTR_TIMING_ROM"), Renderer::Scanline);
+        let mut output = String::new();
+        while cpu.get_cycles() < ROM_CYCLE_LIMIT && !output.contains("Passed") && !output.contains("Failed") {
+            cpu.step();
+            if cpu.bus.serial.output().len() != output.len() {
+                output = String::from_utf8_lossy(cpu.bus.serial.output()).into_owned();
+            }
+        }
+        assert!(output.contains("Passed"), "{}", output);
+    }
+
+    #[test]
+    #[ignore]
+    fn mooneye_timer() {
+        // Every ROM in mooneye's acceptance/timer directory has to end with the Fibonacci signature
+        let dir = std::env::var("MOONEYE_TIMER_DIR").expect("MOONEYE_TIMER_DIR is not set");
+        let mut roms: Vec<_> = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "gb"))
+            .collect();
+        roms.sort();
+        assert!(!roms.is_empty(), "No ROMs in {}", dir);
+
+        let failed: Vec<String> = roms.iter()
+            .filter(|path| run_mooneye(std::fs::read(path).unwrap()) != MOONEYE_PASS)
+            .map(|path| path.display().to_string())
+            .collect();
+        assert!(failed.is_empty(), "Failed: {:?}", failed);
+    }
+}